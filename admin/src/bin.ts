@@ -8,7 +8,7 @@ import yargs from "yargs";
 import { hideBin } from "yargs/helpers";
 import { z } from "zod";
 
-import Mercury, { keyTypes } from "./index.js";
+import Mercury, { Capability } from "./index.js";
 
 const env = z
   .object({
@@ -142,20 +142,27 @@ function keyCommands(yargs: Argv) {
       })
       // key.create
       .command(
-        "create [type] [channels..]",
+        "create [capabilities] [channels..]",
         "create a key",
         function (yargs) {
           return yargs
-            .positional("type", { choices: keyTypes })
+            .positional("capabilities", {
+              describe: "comma-separated list of capabilities",
+              coerce(arg: string) {
+                return z.array(Capability).min(1).parse(arg.split(","));
+              },
+            })
             .positional("channels", {
+              describe: "channels to grant, none to grant them later",
+              default: [] as Array<string>,
               coerce(arg) {
-                return z.array(Uuid).min(1).parse(arg);
+                return z.array(Uuid).parse(arg);
               },
             })
-            .demandOption(["type", "channels"]);
+            .demandOption(["capabilities"]);
         },
         async function (argv) {
-          console.log(await mercury.key.create(argv.type, argv.channels));
+          console.log(await mercury.key.create(argv.capabilities, argv.channels));
         }
       )
       // key.listChannels
@@ -2,44 +2,90 @@ import { z } from "zod";
 
 const Uuid = z.string().uuid();
 
+const Role = z.enum([
+  "owner",
+  "admin",
+  "channel-manager",
+  "key-manager",
+  "viewer",
+]);
+type Role = z.infer<typeof Role>;
+
 const User = z.object({
   id: Uuid,
   name: z.string(),
+  parentId: Uuid.nullable(),
+  role: Role,
+  projectId: Uuid.nullable(),
+  mustChangePassword: z.boolean(),
+  totpEnabled: z.boolean(),
 });
 type User = z.infer<typeof User>;
 
+const TotpEnrolment = z.object({
+  secret: z.string(),
+  uri: z.string(),
+});
+type TotpEnrolment = z.infer<typeof TotpEnrolment>;
+
 const Channel = z.object({
   id: Uuid,
   name: z.string(),
   // TODO: JSON-schema type
   schema: z.record(z.unknown()),
+  public: z.boolean(),
+  authorizationUrl: z.string().nullable(),
+  projectId: Uuid,
 });
 type Channel = z.infer<typeof Channel>;
 
-export const keyTypes = ["publisher", "subscriber"] as const;
-const KeyType = z.enum(keyTypes);
-type KeyType = z.infer<typeof KeyType>;
+export const capabilities = ["publish", "subscribe", "presence", "history"] as const;
+export const Capability = z.enum(capabilities);
+type Capability = z.infer<typeof Capability>;
 
 const Key = z.object({
   id: Uuid,
-  type: KeyType,
+  capabilities: z.array(Capability),
+  projectId: Uuid,
 });
 type Key = z.infer<typeof Key>;
 
+const AccessTokenScope = z.enum(["full", "read-only", "channels"]);
+type AccessTokenScope = z.infer<typeof AccessTokenScope>;
+
+const AccessToken = z.object({
+  id: Uuid,
+  name: z.string(),
+  scope: AccessTokenScope,
+  createdAt: z.string(),
+  expiresAt: z.string().nullable(),
+  lastUsedAt: z.string().nullable(),
+});
+type AccessToken = z.infer<typeof AccessToken>;
+
+const Invitation = z.object({
+  id: Uuid,
+  parentId: Uuid,
+  role: Role,
+  projectId: Uuid.nullable(),
+  createdAt: z.string(),
+  expiresAt: z.string(),
+});
+type Invitation = z.infer<typeof Invitation>;
+
 export default class Mercury {
   #url: string;
   #name: string;
   #password: string;
+  #sessionToken?: string;
 
   #user: UserDelegate;
   #channel: ChannelDelegate;
   #key: KeyDelegate;
+  #accessToken: AccessTokenDelegate;
+  #invitation: InvitationDelegate;
 
   constructor(url: string, name: string, password: string) {
-    if (password === "mercury") {
-      console.warn("WARNING: you are using the default password for the admin user.");
-      console.warn("WARNING: you should change your password.");
-    }
     this.#url = url;
     this.#name = name;
     this.#password = password;
@@ -54,9 +100,65 @@ export default class Mercury {
     );
     this.#channel = new ChannelDelegate(url, authorizationHeader);
     this.#key = new KeyDelegate(url, authorizationHeader);
+    this.#accessToken = new AccessTokenDelegate(url, authorizationHeader);
+    this.#invitation = new InvitationDelegate(url, authorizationHeader);
+  }
+
+  /** Log in and use a session token instead of keeping the password. */
+  static async login(
+    url: string,
+    name: string,
+    password: string,
+    totpCode?: string
+  ): Promise<Mercury> {
+    const response = await fetch(new URL("/api/auth/login", url).href, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ name, password, totpCode }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    const { token } = z.object({ token: z.string() }).parse(await response.json());
+    const mercury = new Mercury(url, name, "");
+    mercury.#sessionToken = token;
+    mercury.#updatePassword("");
+    return mercury;
+  }
+
+  /** Authenticate with an access token instead of a password. */
+  static withAccessToken(url: string, token: string): Mercury {
+    const mercury = new Mercury(url, "", "");
+    mercury.#sessionToken = token;
+    mercury.#updatePassword("");
+    return mercury;
+  }
+
+  /** Redeem an invitation, creating a user with the chosen name and password. */
+  static async redeemInvitation(
+    url: string,
+    token: string,
+    name: string,
+    password: string
+  ): Promise<Mercury> {
+    const response = await fetch(new URL("/api/invitations/redeem", url).href, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ token, name, password }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return new Mercury(url, name, password);
+  }
+
+  async logout(): Promise<void> {
+    const url = new URL("/api/auth/logout", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: { Authorization: this.#authorizationHeader() },
+    });
+    if (!response.ok) throw new Error(await response.text());
   }
 
   #authorizationHeader(): string {
+    if (this.#sessionToken !== undefined) return `Bearer ${this.#sessionToken}`;
     return `Basic ${Buffer.from(`${this.#name}:${this.#password}`, "utf8").toString("base64")}`;
   }
 
@@ -71,10 +173,13 @@ export default class Mercury {
     );
     this.#channel = new ChannelDelegate(this.#url, authorizationHeader);
     this.#key = new KeyDelegate(this.#url, authorizationHeader);
+    this.#accessToken = new AccessTokenDelegate(this.#url, authorizationHeader);
+    this.#invitation = new InvitationDelegate(this.#url, authorizationHeader);
   }
 
   #updatePassword(password: string) {
-    this.#password = password;
+    // the session stays open after a password change
+    this.#password = this.#sessionToken === undefined ? password : "";
     const authorizationHeader = this.#authorizationHeader();
     this.#user = new UserDelegate(
       this.#url,
@@ -84,6 +189,8 @@ export default class Mercury {
     );
     this.#channel = new ChannelDelegate(this.#url, authorizationHeader);
     this.#key = new KeyDelegate(this.#url, authorizationHeader);
+    this.#accessToken = new AccessTokenDelegate(this.#url, authorizationHeader);
+    this.#invitation = new InvitationDelegate(this.#url, authorizationHeader);
   }
 
   get user(): UserDelegate {
@@ -97,6 +204,14 @@ export default class Mercury {
   get key(): KeyDelegate {
     return this.#key;
   }
+
+  get accessToken(): AccessTokenDelegate {
+    return this.#accessToken;
+  }
+
+  get invitation(): InvitationDelegate {
+    return this.#invitation;
+  }
 }
 
 class UserDelegate {
@@ -128,7 +243,11 @@ class UserDelegate {
     return z.array(User).parse(await response.json());
   }
 
-  async create(name: string, password: string): Promise<User> {
+  async create(
+    name: string,
+    password: string,
+    role: Role = "viewer",
+  ): Promise<User> {
     const url = new URL("/api/users", this.#url);
     const response = await fetch(url.href, {
       method: "POST",
@@ -136,7 +255,7 @@ class UserDelegate {
         Authorization: this.#authorizationHeader,
         "Content-Type": "application/json",
       },
-      body: JSON.stringify({ name, password }),
+      body: JSON.stringify({ name, password, role }),
     });
     if (!response.ok) throw new Error(await response.text());
     return User.parse(await response.json());
@@ -170,8 +289,99 @@ class UserDelegate {
     this.#updatePassword(password);
   }
 
-  async delete(id: string): Promise<void> {
+  /** Start enrolling TOTP, returning the secret and its otpauth URI. */
+  async startTotpEnrolment(): Promise<TotpEnrolment> {
+    const url = new URL("/api/users/totp", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return TotpEnrolment.parse(await response.json());
+  }
+
+  /** Confirm the TOTP enrolment, returning recovery codes. */
+  async confirmTotpEnrolment(code: string): Promise<Array<string>> {
+    const url = new URL("/api/users/totp/confirm", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ code }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return z.array(z.string()).parse(await response.json());
+  }
+
+  async newRecoveryCodes(): Promise<Array<string>> {
+    const url = new URL("/api/users/totp/recovery-codes", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return z.array(z.string()).parse(await response.json());
+  }
+
+  /** Disable TOTP for self, given a TOTP code or an unused recovery code. */
+  async disableTotp(code: string): Promise<void> {
+    const url = new URL("/api/users/totp", this.#url);
+    const response = await fetch(url.href, {
+      method: "DELETE",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ code }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+  }
+
+  /** Disable TOTP for a descendant user. */
+  async disableOtherTotp(id: string): Promise<void> {
+    const url = new URL(`/api/users/${id}/totp`, this.#url);
+    const response = await fetch(url.href, {
+      method: "DELETE",
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+  }
+
+  async setRole(id: string, role: Role): Promise<User> {
+    const url = new URL(`/api/users/${id}/role`, this.#url);
+    const response = await fetch(url.href, {
+      method: "PATCH",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ role }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return User.parse(await response.json());
+  }
+
+  /** Move a user under another parent. */
+  async setParent(id: string, parentId: string): Promise<User> {
+    const url = new URL(`/api/users/${id}/parent`, this.#url);
+    const response = await fetch(url.href, {
+      method: "PATCH",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ parentId }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return User.parse(await response.json());
+  }
+
+  /** Delete a user, giving their children, channels and keys to the heir, or to their parent. */
+  async delete(id: string, heir?: string): Promise<void> {
     const url = new URL(`/api/users/${id}`, this.#url);
+    if (heir !== undefined) url.searchParams.set("heir", heir);
     const response = await fetch(url.href, {
       method: "DELETE",
       headers: { Authorization: this.#authorizationHeader },
@@ -198,7 +408,12 @@ class ChannelDelegate {
     return z.array(Channel).parse(await response.json());
   }
 
-  async create(name: string, schema: Record<string, unknown>): Promise<Channel> {
+  async create(
+    name: string,
+    schema: Record<string, unknown>,
+    isPublic = false,
+    projectId?: string,
+  ): Promise<Channel> {
     const url = new URL("/api/channels", this.#url);
     const response = await fetch(url.href, {
       method: "POST",
@@ -206,7 +421,21 @@ class ChannelDelegate {
         Authorization: this.#authorizationHeader,
         "Content-Type": "application/json",
       },
-      body: JSON.stringify({ name, schema }),
+      body: JSON.stringify({ projectId, name, schema, public: isPublic }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return Channel.parse(await response.json());
+  }
+
+  async setPublic(id: string, isPublic: boolean): Promise<Channel> {
+    const url = new URL(`/api/channels/${id}`, this.#url);
+    const response = await fetch(url.href, {
+      method: "PATCH",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ public: isPublic }),
     });
     if (!response.ok) throw new Error(await response.text());
     return Channel.parse(await response.json());
@@ -240,7 +469,11 @@ class KeyDelegate {
     return z.array(Key).parse(await response.json());
   }
 
-  async create(type: KeyType, channels: Array<string>): Promise<string> {
+  async create(
+    capabilities: Array<Capability>,
+    channels: Array<string>,
+    projectId?: string,
+  ): Promise<string> {
     const url = new URL("/api/keys", this.#url);
     const response = await fetch(url.href, {
       method: "POST",
@@ -248,7 +481,7 @@ class KeyDelegate {
         Authorization: this.#authorizationHeader,
         "Content-Type": "application/json",
       },
-      body: JSON.stringify({ type, channels }),
+      body: JSON.stringify({ projectId, capabilities, channels }),
     });
     if (!response.ok) throw new Error(await response.text());
     return await response.text();
@@ -272,3 +505,97 @@ class KeyDelegate {
     if (!response.ok) throw new Error(await response.text());
   }
 }
+
+class AccessTokenDelegate {
+  #url: string;
+  #authorizationHeader: string;
+
+  constructor(url: string, authorizationHeader: string) {
+    this.#url = url;
+    this.#authorizationHeader = authorizationHeader;
+  }
+
+  async list(): Promise<Array<AccessToken>> {
+    const url = new URL("/api/access-tokens", this.#url);
+    const response = await fetch(url.href, {
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return z.array(AccessToken).parse(await response.json());
+  }
+
+  /** Create an access token, returning it with its value, which will not be returned again. */
+  async create(
+    name: string,
+    scope: AccessTokenScope,
+    expiresAt?: Date
+  ): Promise<AccessToken & { token: string }> {
+    const url = new URL("/api/access-tokens", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ name, scope, expiresAt }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return AccessToken.extend({ token: z.string() }).parse(await response.json());
+  }
+
+  async revoke(id: string): Promise<void> {
+    const url = new URL(`/api/access-tokens/${id}`, this.#url);
+    const response = await fetch(url.href, {
+      method: "DELETE",
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+  }
+}
+
+class InvitationDelegate {
+  #url: string;
+  #authorizationHeader: string;
+
+  constructor(url: string, authorizationHeader: string) {
+    this.#url = url;
+    this.#authorizationHeader = authorizationHeader;
+  }
+
+  /** List the pending invitations. */
+  async list(): Promise<Array<Invitation>> {
+    const url = new URL("/api/invitations", this.#url);
+    const response = await fetch(url.href, {
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return z.array(Invitation).parse(await response.json());
+  }
+
+  /** Create an invitation, returning it with its token, which will not be returned again. */
+  async create(
+    role: Role,
+    options: { parentId?: string; projectId?: string; expiresAt?: Date } = {}
+  ): Promise<Invitation & { token: string }> {
+    const url = new URL("/api/invitations", this.#url);
+    const response = await fetch(url.href, {
+      method: "POST",
+      headers: {
+        Authorization: this.#authorizationHeader,
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ role, ...options }),
+    });
+    if (!response.ok) throw new Error(await response.text());
+    return Invitation.extend({ token: z.string() }).parse(await response.json());
+  }
+
+  async revoke(id: string): Promise<void> {
+    const url = new URL(`/api/invitations/${id}`, this.#url);
+    const response = await fetch(url.href, {
+      method: "DELETE",
+      headers: { Authorization: this.#authorizationHeader },
+    });
+    if (!response.ok) throw new Error(await response.text());
+  }
+}
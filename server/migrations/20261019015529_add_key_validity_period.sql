@@ -0,0 +1,4 @@
+ALTER TABLE "Key"
+    ADD COLUMN not_before    timestamptz,
+    ADD COLUMN expires_at    timestamptz,
+    ADD CONSTRAINT "Key_validity_period_check" CHECK (not_before < expires_at);
@@ -0,0 +1,3 @@
+ALTER TABLE "Key"
+    ADD COLUMN previous_hash                 char(34),
+    ADD COLUMN previous_secret_expires_at    timestamptz;
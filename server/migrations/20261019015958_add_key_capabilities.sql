@@ -0,0 +1,27 @@
+CREATE TYPE capability AS ENUM ('publish', 'subscribe', 'presence', 'history');
+
+ALTER TABLE "Key"
+    ADD COLUMN capabilities    capability[]    NOT NULL DEFAULT '{}';
+
+UPDATE "Key"
+    SET capabilities = CASE type
+        WHEN 'publisher' THEN '{publish}'::capability[]
+        ELSE '{subscribe}'::capability[]
+    END;
+
+ALTER TABLE "Key"
+    ALTER COLUMN capabilities DROP DEFAULT,
+    DROP COLUMN type;
+
+DROP TYPE keytype;
+
+ALTER TABLE "Access"
+    ADD COLUMN capabilities    capability[]    NOT NULL DEFAULT '{}';
+
+UPDATE "Access"
+    SET capabilities = "Key".capabilities
+    FROM "Key"
+    WHERE "Key".id = "Access".key_id;
+
+ALTER TABLE "Access"
+    ALTER COLUMN capabilities DROP DEFAULT;
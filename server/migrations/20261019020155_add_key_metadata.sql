@@ -0,0 +1,9 @@
+ALTER TABLE "Key"
+    ADD COLUMN name               varchar(64),
+    ADD COLUMN description        text,
+    ADD COLUMN labels             varchar(32)[]    NOT NULL DEFAULT '{}',
+    ADD COLUMN created_at         timestamptz      NOT NULL DEFAULT now(),
+    ADD COLUMN created_by         uuid             REFERENCES "User" ON DELETE SET NULL,
+    ADD COLUMN last_used_at       timestamptz,
+    ADD COLUMN publish_count      bigint           NOT NULL DEFAULT 0,
+    ADD COLUMN subscribe_count    bigint           NOT NULL DEFAULT 0;
@@ -0,0 +1,16 @@
+-- whether a channel name matches a pattern, where `*` matches any sequence of characters
+CREATE FUNCTION channel_name_matches(name text, pattern text) RETURNS boolean AS $$
+    SELECT name LIKE replace(
+        replace(replace(replace(pattern, '\', '\\'), '%', '\%'), '_', '\_'),
+        '*',
+        '%'
+    )
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE TABLE "PatternAccess" (
+    key_id          uuid            REFERENCES "Key" ON DELETE CASCADE NOT NULL,
+    pattern         varchar(16)     NOT NULL CHECK (length(pattern) >= 1),
+    capabilities    capability[]    NOT NULL,
+
+    PRIMARY KEY (key_id, pattern)
+);
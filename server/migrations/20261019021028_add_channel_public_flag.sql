@@ -0,0 +1 @@
+ALTER TABLE "Channel" ADD COLUMN public boolean NOT NULL DEFAULT false;
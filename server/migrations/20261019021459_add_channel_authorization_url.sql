@@ -0,0 +1 @@
+ALTER TABLE "Channel" ADD COLUMN authorization_url text;
@@ -0,0 +1,13 @@
+ALTER TABLE "Key"
+    ADD COLUMN publish_rate_limit double precision CHECK (publish_rate_limit > 0),
+    ADD COLUMN publish_byte_rate_limit double precision CHECK (publish_byte_rate_limit > 0),
+    ADD COLUMN daily_quota bigint CHECK (daily_quota >= 0),
+    ADD COLUMN monthly_quota bigint CHECK (monthly_quota >= 0),
+    ADD COLUMN daily_quota_used bigint NOT NULL DEFAULT 0,
+    ADD COLUMN monthly_quota_used bigint NOT NULL DEFAULT 0,
+    ADD COLUMN quota_day date NOT NULL DEFAULT CURRENT_DATE,
+    ADD COLUMN quota_month date NOT NULL DEFAULT date_trunc('month', CURRENT_DATE);
+
+ALTER TABLE "Channel"
+    ADD COLUMN publish_rate_limit double precision CHECK (publish_rate_limit > 0),
+    ADD COLUMN publish_byte_rate_limit double precision CHECK (publish_byte_rate_limit > 0);
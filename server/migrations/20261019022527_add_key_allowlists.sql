@@ -0,0 +1,3 @@
+ALTER TABLE "Key"
+    ADD COLUMN allowed_origins varchar(256)[] NOT NULL DEFAULT '{}',
+    ADD COLUMN allowed_ips cidr[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,6 @@
+CREATE TYPE role AS ENUM ('owner', 'admin', 'channel-manager', 'key-manager', 'viewer');
+
+ALTER TABLE "User" ADD COLUMN role role NOT NULL DEFAULT 'viewer';
+
+-- existing users could manage everything
+UPDATE "User" SET role = CASE WHEN rank = 0 THEN 'owner'::role ELSE 'admin'::role END;
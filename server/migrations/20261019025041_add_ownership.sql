@@ -0,0 +1,33 @@
+ALTER TABLE "Channel"
+    ADD COLUMN created_by    uuid    REFERENCES "User" ON DELETE SET NULL;
+
+-- existing channels and keys belong to the root user
+UPDATE "Channel" SET created_by = (SELECT id FROM "User" WHERE rank = 0 LIMIT 1);
+UPDATE "Key" SET created_by = (SELECT id FROM "User" WHERE rank = 0 LIMIT 1)
+    WHERE created_by IS NULL;
+
+-- whether a user manages the channels and keys created by another user: they created them, or
+-- they rank higher than their creator (anything without a creator is managed by the root users)
+CREATE FUNCTION user_manages(user_id uuid, created_by uuid) RETURNS boolean AS $$
+    SELECT COALESCE(created_by = user_id, false) OR EXISTS (
+        SELECT FROM "User" AS manager
+            WHERE manager.id = user_id AND manager.rank < COALESCE(
+                (SELECT rank FROM "User" WHERE id = created_by),
+                1
+            )
+    )
+$$ LANGUAGE SQL STABLE;
+
+CREATE TABLE "ChannelCollaborator" (
+    channel_id    uuid    REFERENCES "Channel" ON DELETE CASCADE NOT NULL,
+    user_id       uuid    REFERENCES "User" ON DELETE CASCADE NOT NULL,
+
+    PRIMARY KEY (channel_id, user_id)
+);
+
+CREATE TABLE "KeyCollaborator" (
+    key_id     uuid    REFERENCES "Key" ON DELETE CASCADE NOT NULL,
+    user_id    uuid    REFERENCES "User" ON DELETE CASCADE NOT NULL,
+
+    PRIMARY KEY (key_id, user_id)
+);
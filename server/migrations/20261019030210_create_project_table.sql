@@ -0,0 +1,41 @@
+CREATE TABLE "Project" (
+    id            uuid           PRIMARY KEY DEFAULT gen_random_uuid(),
+    name          varchar(16)    UNIQUE NOT NULL CHECK (length(name) >= 4),
+    created_at    timestamptz    NOT NULL DEFAULT now()
+);
+
+-- existing channels and keys move to a default project, existing users can access all projects
+INSERT INTO "Project" (name) VALUES ('default');
+
+ALTER TABLE "Channel"
+    ADD COLUMN project_id    uuid    REFERENCES "Project" ON DELETE CASCADE;
+UPDATE "Channel" SET project_id = (SELECT id FROM "Project");
+ALTER TABLE "Channel"
+    ALTER COLUMN project_id SET NOT NULL,
+    DROP CONSTRAINT "Channel_name_key",
+    ADD CONSTRAINT "Channel_project_id_name_key" UNIQUE (project_id, name);
+
+ALTER TABLE "Key"
+    ADD COLUMN project_id    uuid    REFERENCES "Project" ON DELETE CASCADE;
+UPDATE "Key" SET project_id = (SELECT id FROM "Project");
+ALTER TABLE "Key"
+    ALTER COLUMN project_id SET NOT NULL;
+
+ALTER TABLE "User"
+    ADD COLUMN project_id    uuid    REFERENCES "Project" ON DELETE CASCADE;
+
+-- whether a user manages the channels and keys of a project created by another user: they can
+-- access the project, and they created them or rank higher than their creator (anything without a
+-- creator is managed by the root users)
+DROP FUNCTION user_manages(uuid, uuid);
+CREATE FUNCTION user_manages(user_id uuid, created_by uuid, project_id uuid) RETURNS boolean AS $$
+    SELECT EXISTS (
+        SELECT FROM "User" AS manager
+            WHERE manager.id = $1
+                AND (manager.project_id IS NULL OR manager.project_id = $3)
+                AND (
+                    manager.id = $2
+                        OR manager.rank < COALESCE((SELECT rank FROM "User" WHERE id = $2), 1)
+                )
+    )
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,15 @@
+ALTER TABLE "User"
+    ADD COLUMN must_change_password    boolean    NOT NULL DEFAULT false;
+
+-- the root user is now created on the first start of the server, remove the one with the default
+-- password from fresh databases
+DELETE FROM "User"
+    WHERE name = 'admin'
+        AND password_hash = crypt('mercury', password_hash)
+        AND (SELECT COUNT(*) FROM "User") = 1
+        AND NOT EXISTS (SELECT FROM "Channel")
+        AND NOT EXISTS (SELECT FROM "Key");
+
+-- existing users with the default password must change it
+UPDATE "User" SET must_change_password = true
+    WHERE password_hash = crypt('mercury', password_hash);
@@ -0,0 +1,10 @@
+ALTER TABLE "User"
+    ADD COLUMN totp_secret     bytea,
+    ADD COLUMN totp_enabled    boolean    NOT NULL DEFAULT false;
+
+CREATE TABLE "RecoveryCode" (
+    user_id      uuid     REFERENCES "User" ON DELETE CASCADE NOT NULL,
+    code_hash    bytea    NOT NULL,
+
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,14 @@
+CREATE TYPE access_token_scope AS ENUM ('full', 'read-only', 'channels');
+
+CREATE TABLE "AccessToken" (
+    id              uuid                  PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id         uuid                  REFERENCES "User" ON DELETE CASCADE NOT NULL,
+    name            varchar(64)           NOT NULL,
+    token_hash      bytea                 NOT NULL UNIQUE,
+    scope           access_token_scope    NOT NULL,
+    created_at      timestamptz           NOT NULL DEFAULT now(),
+    expires_at      timestamptz,
+    last_used_at    timestamptz,
+
+    UNIQUE (user_id, name)
+);
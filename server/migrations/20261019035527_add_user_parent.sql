@@ -0,0 +1,37 @@
+-- users without a parent are root users
+ALTER TABLE "User"
+    ADD COLUMN parent_id    uuid    REFERENCES "User" ON DELETE RESTRICT;
+
+-- the parents of existing users are unknown, they move under the root user
+UPDATE "User" SET parent_id = (SELECT id FROM "User" WHERE rank = 0 LIMIT 1) WHERE rank > 0;
+
+ALTER TABLE "User" DROP COLUMN rank;
+
+CREATE INDEX ON "User" (parent_id);
+
+-- whether a user is another user or one of its descendants
+CREATE FUNCTION user_descends_from(user_id uuid, ancestor_id uuid) RETURNS boolean AS $$
+    WITH RECURSIVE ancestor AS (
+        SELECT id, parent_id FROM "User" WHERE id = $1
+        UNION
+        SELECT "User".id, "User".parent_id FROM "User"
+            JOIN ancestor ON "User".id = ancestor.parent_id
+    )
+    SELECT EXISTS (SELECT FROM ancestor WHERE id = $2)
+$$ LANGUAGE SQL STABLE;
+
+-- whether a user manages the channels and keys of a project created by another user: they can
+-- access the project, and they created them or are an ancestor of their creator (anything without
+-- a creator is managed by the root users)
+CREATE OR REPLACE FUNCTION user_manages(user_id uuid, created_by uuid, project_id uuid)
+RETURNS boolean AS $$
+    SELECT EXISTS (
+        SELECT FROM "User" AS manager
+            WHERE manager.id = $1
+                AND (manager.project_id IS NULL OR manager.project_id = $3)
+                AND CASE
+                    WHEN $2 IS NULL THEN manager.parent_id IS NULL
+                    ELSE user_descends_from($2, manager.id)
+                END
+    )
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,12 @@
+CREATE TABLE "Invitation" (
+    id            uuid           PRIMARY KEY DEFAULT gen_random_uuid(),
+    token_hash    bytea          NOT NULL UNIQUE,
+    -- the user the invitee will be a child of
+    parent_id     uuid           REFERENCES "User" ON DELETE CASCADE NOT NULL,
+    role          role           NOT NULL,
+    project_id    uuid           REFERENCES "Project" ON DELETE CASCADE,
+    created_at    timestamptz    NOT NULL DEFAULT now(),
+    expires_at    timestamptz    NOT NULL
+);
+
+CREATE INDEX ON "Invitation" (parent_id);
@@ -0,0 +1,119 @@
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+use self::error::Result;
+use crate::api::extract::validated_json::ValidatedJson;
+use crate::models::access_token::{AccessToken, Scope};
+use crate::models::user::{Permission, User};
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state)
+        .route("/", get(list_access_tokens).post(create_access_token))
+        .route("/:id", delete(revoke_access_token))
+}
+
+/// Get all own access tokens.
+#[instrument]
+async fn list_access_tokens(
+    State(state): State<SharedState>,
+    user: User,
+) -> Result<Json<Vec<AccessToken>>> {
+    user.require(Permission::ManageAccount)?;
+    Ok(Json(
+        AccessToken::get_all(&state.read().await.pool, &user).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct CreateAccessTokenBody {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    scope: Scope,
+    /// Never expires if not set.
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateAccessTokenResponse {
+    #[serde(flatten)]
+    access_token: AccessToken,
+    token: String,
+}
+
+/// Create an access token, returning its value, to use as a Bearer token.
+///
+/// The value will not be returned again.
+#[instrument]
+async fn create_access_token(
+    State(state): State<SharedState>,
+    user: User,
+    ValidatedJson(body): ValidatedJson<CreateAccessTokenBody>,
+) -> Result<(StatusCode, Json<CreateAccessTokenResponse>)> {
+    user.require(Permission::ManageAccount)?;
+    let (access_token, token) = AccessToken::new(
+        &state.read().await.pool,
+        &user,
+        &body.name,
+        body.scope,
+        body.expires_at,
+    )
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateAccessTokenResponse {
+            access_token,
+            token,
+        }),
+    ))
+}
+
+/// Revoke an own access token.
+#[instrument]
+async fn revoke_access_token(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageAccount)?;
+    let access_token = AccessToken::get(&state.read().await.pool, &user, id).await?;
+    access_token.delete(&state.read().await.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use tracing::debug;
+
+    use crate::models::{access_token, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        AccessTokenError(#[from] access_token::error::Error),
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::AccessTokenError(error) => error.into_response(),
+            }
+        }
+    }
+}
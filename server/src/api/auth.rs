@@ -0,0 +1,119 @@
+use axum::extract::State;
+use axum::http::header::{HeaderName, SET_COOKIE};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use validator::Validate;
+
+use self::error::Result;
+use crate::api::extract::client_ip::ClientIp;
+use crate::api::extract::validated_json::ValidatedJson;
+use crate::config::CONFIG;
+use crate::models::session::{Session, SESSION_COOKIE};
+use crate::models::user::User;
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state)
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+}
+
+/// A `Set-Cookie` header.
+type SetCookie = [(HeaderName, String); 1];
+
+/// Format the session cookie, which expires after `max_age` seconds.
+fn session_cookie(token: &str, max_age: i64) -> String {
+    format!(
+        "{SESSION_COOKIE}={token}; Max-Age={max_age}; Path=/api; HttpOnly; Secure; SameSite=Strict"
+    )
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct LoginBody {
+    #[validate(length(min = 1))]
+    name: String,
+    #[validate(length(min = 1))]
+    password: String,
+    /// A TOTP code or a recovery code, required if the user enrolled TOTP.
+    totp_code: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginResponse {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Open a session, returning its token both in the body and in the `mercury_session` cookie.
+///
+/// The token can then be used as a Bearer token instead of Basic credentials.
+#[instrument(skip(body))]
+async fn login(
+    State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
+    ValidatedJson(body): ValidatedJson<LoginBody>,
+) -> Result<(SetCookie, Json<LoginResponse>)> {
+    let user = User::authenticate(
+        &state,
+        ip,
+        &body.name,
+        &body.password,
+        body.totp_code.as_deref(),
+    )
+    .await?;
+    let (session, token) = Session::new(&state.read().await.pool, &user).await?;
+    let max_age = i64::from(CONFIG.session_lifetime_hours) * 60 * 60;
+    Ok((
+        [(SET_COOKIE, session_cookie(token.as_ref(), max_age))],
+        Json(LoginResponse {
+            token: token.as_ref().to_owned(),
+            expires_at: session.expires_at,
+        }),
+    ))
+}
+
+/// Close the current session.
+#[instrument]
+async fn logout(
+    State(state): State<SharedState>,
+    session: Session,
+) -> Result<(StatusCode, SetCookie)> {
+    session.delete(&state.read().await.pool).await?;
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(SET_COOKIE, session_cookie("", 0))],
+    ))
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use tracing::debug;
+
+    use crate::models::{session, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        SessionError(#[from] session::error::Error),
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::SessionError(error) => error.into_response(),
+            }
+        }
+    }
+}
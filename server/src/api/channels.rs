@@ -1,24 +1,37 @@
-use axum::extract::{Path, State};
-use axum::routing::{delete, get};
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, put};
 use axum::{Json, Router};
 use hyper::StatusCode;
 use jsonschema::JSONSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use tracing::instrument;
 use uuid::Uuid;
 use validator::{Validate, ValidationError};
 
-use self::error::Result;
+use self::error::{Error, Result};
 use crate::api::extract::validated_json::ValidatedJson;
-use crate::models::channel::Channel;
-use crate::models::user::User;
+use crate::models::channel::{Channel, ChannelLimits};
+use crate::models::key::Key;
+use crate::models::project::Project;
+use crate::models::user::{self, Permission, User};
 use crate::state::SharedState;
 
 pub(crate) fn app(state: SharedState) -> Router<SharedState> {
     Router::with_state(state)
         .route("/", get(list_channels).post(create_channel))
-        .route("/:id", delete(delete_channel))
+        .route(
+            "/:id",
+            get(get_channel)
+                .patch(update_channel)
+                .delete(delete_channel),
+        )
+        .route("/:id/keys", get(list_keys))
+        .route("/:id/collaborators", get(list_collaborators))
+        .route(
+            "/:id/collaborators/:user_id",
+            put(add_collaborator).delete(remove_collaborator),
+        )
 }
 
 /// Validate the JSON schema (for the validator crate).
@@ -29,29 +42,175 @@ fn validate_schema(schema: &Value) -> std::result::Result<(), ValidationError> {
         .map_err(|_| ValidationError::new("Invalid schema"))
 }
 
-/// Get all channels.
+#[derive(Debug, Deserialize)]
+struct ListChannelsQuery {
+    project: Option<Uuid>,
+}
+
+/// Get all channels that the user can see, optionally only those of a project.
 #[instrument]
-async fn list_channels(State(state): State<SharedState>, user: User) -> Result<Json<Vec<Channel>>> {
-    Ok(Json(Channel::get_all(&state.read().await.pool).await?))
+async fn list_channels(
+    State(state): State<SharedState>,
+    user: User,
+    Query(query): Query<ListChannelsQuery>,
+) -> Result<Json<Vec<Channel>>> {
+    user.require(Permission::ViewChannels)?;
+    Ok(Json(
+        Channel::get_all(&state.read().await.pool, &user, query.project).await?,
+    ))
+}
+
+/// Deserialize a present field, null included, as `Some` (for double options).
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
 struct CreateChannelBody {
+    /// Defaults to the user's project.
+    project_id: Option<Uuid>,
     #[validate(length(min = 4, max = 16))]
     name: String,
     #[validate(custom = "validate_schema")]
     schema: Value,
+    /// Whether the channel can be subscribed to without a key.
+    #[serde(default)]
+    public: bool,
+    /// The URL that decides whether subscribers can subscribe to the channel.
+    #[validate(url)]
+    authorization_url: Option<String>,
+    /// Messages per second.
+    publish_rate_limit: Option<f64>,
+    /// Bytes per second.
+    publish_byte_rate_limit: Option<f64>,
 }
 
-/// Create a channel.
+/// Create a channel in a project.
 #[instrument]
 async fn create_channel(
     State(state): State<SharedState>,
     user: User,
     ValidatedJson(body): ValidatedJson<CreateChannelBody>,
 ) -> Result<Json<Channel>> {
+    user.require(Permission::ManageChannels)?;
+    let project = Project::get(&state.read().await.pool, user.project(body.project_id)?).await?;
     Ok(Json(
-        Channel::new(&state.read().await.pool, &body.name, &body.schema).await?,
+        Channel::new(
+            &state.read().await.pool,
+            &user,
+            project.id,
+            &body.name,
+            &body.schema,
+            body.public,
+            body.authorization_url.as_deref(),
+            ChannelLimits {
+                publish_rate_limit: body.publish_rate_limit,
+                publish_byte_rate_limit: body.publish_byte_rate_limit,
+            },
+        )
+        .await?,
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChannelDetails {
+    #[serde(flatten)]
+    channel: Channel,
+    /// The number of keys that have access to the channel.
+    key_count: i64,
+    /// The number of live subscriptions to the channel.
+    subscriber_count: usize,
+}
+
+/// Get a channel, with how many keys and subscribers it has.
+#[instrument]
+async fn get_channel(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ChannelDetails>> {
+    user.require(Permission::ViewChannels)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    let key_count = Key::count_from_channel(&state.read().await.pool, &channel).await?;
+    let subscriber_count = state.read().await.senders.subscriber_count(&channel);
+    Ok(Json(ChannelDetails {
+        channel,
+        key_count,
+        subscriber_count,
+    }))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct UpdateChannelBody {
+    public: Option<bool>,
+    /// Set to null to remove the authorization webhook.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[validate(url)]
+    authorization_url: Option<Option<String>>,
+    /// Set to null to remove the limit.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    publish_rate_limit: Option<Option<f64>>,
+    /// Set to null to remove the limit.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    publish_byte_rate_limit: Option<Option<f64>>,
+}
+
+/// Make a channel public or private, or change its authorization webhook or its publish rate
+/// limits.
+#[instrument]
+async fn update_channel(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<UpdateChannelBody>,
+) -> Result<Json<Channel>> {
+    user.require(Permission::ManageChannels)?;
+    let mut channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    if let Some(public) = body.public {
+        channel.set_public(&state.read().await.pool, public).await?;
+    }
+    if let Some(authorization_url) = body.authorization_url {
+        channel
+            .set_authorization_url(&state.read().await.pool, authorization_url)
+            .await?;
+        state
+            .write()
+            .await
+            .authorizations
+            .forget_channel(channel.id);
+    }
+    if body.publish_rate_limit.is_some() || body.publish_byte_rate_limit.is_some() {
+        let limits = ChannelLimits {
+            publish_rate_limit: body
+                .publish_rate_limit
+                .unwrap_or(channel.publish_rate_limit),
+            publish_byte_rate_limit: body
+                .publish_byte_rate_limit
+                .unwrap_or(channel.publish_byte_rate_limit),
+        };
+        channel.set_limits(&state.read().await.pool, limits).await?;
+    }
+    Ok(Json(channel))
+}
+
+/// Get all keys that have access to a channel.
+#[instrument]
+async fn list_keys(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Key>>> {
+    user.require(Permission::ViewKeys)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    Ok(Json(
+        Key::get_from_channel(&state.read().await.pool, &channel, &user).await?,
     ))
 }
 
@@ -62,30 +221,103 @@ async fn delete_channel(
     user: User,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode> {
-    let channel = Channel::get(&state.read().await.pool, id).await?;
+    user.require(Permission::ManageChannels)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
     channel.delete(&state.read().await.pool).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Get the ids of the users a channel is shared with.
+#[instrument]
+async fn list_collaborators(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Uuid>>> {
+    user.require(Permission::ViewChannels)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    Ok(Json(channel.collaborators(&state.read().await.pool).await?))
+}
+
+/// Share a channel with a user.
+///
+/// Only the user who created the channel and their ancestors in the user hierarchy can share it.
+#[instrument]
+async fn add_collaborator(
+    State(state): State<SharedState>,
+    user: User,
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageChannels)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    if !channel
+        .is_managed_by(&state.read().await.pool, &user)
+        .await?
+    {
+        return Err(Error::NotManager);
+    }
+    let collaborator = User::get(&state.read().await.pool, user_id).await?;
+    if !collaborator.can_access(channel.project_id) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    channel
+        .share(&state.read().await.pool, &collaborator)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stop sharing a channel with a user.
+#[instrument]
+async fn remove_collaborator(
+    State(state): State<SharedState>,
+    user: User,
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageChannels)?;
+    let channel = Channel::get_visible(&state.read().await.pool, id, &user).await?;
+    if !channel
+        .is_managed_by(&state.read().await.pool, &user)
+        .await?
+    {
+        return Err(Error::NotManager);
+    }
+    channel.unshare(&state.read().await.pool, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 mod error {
     use axum::response::IntoResponse;
+    use hyper::StatusCode;
     use tracing::debug;
 
-    use crate::models::channel;
+    use crate::models::{channel, key, project, user};
 
     pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+    #[allow(clippy::enum_variant_names)]
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
         #[error(transparent)]
         ChannelError(#[from] channel::error::Error),
+        #[error(transparent)]
+        KeyError(#[from] key::error::Error),
+        #[error(transparent)]
+        ProjectError(#[from] project::error::Error),
+        #[error("Only the creator of the channel and their ancestors can share it")]
+        NotManager,
     }
 
     impl IntoResponse for Error {
         fn into_response(self) -> axum::response::Response {
             debug!(?self);
             match self {
+                Error::UserError(error) => error.into_response(),
                 Error::ChannelError(error) => error.into_response(),
+                Error::KeyError(error) => error.into_response(),
+                Error::ProjectError(error) => error.into_response(),
+                Error::NotManager => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
             }
         }
     }
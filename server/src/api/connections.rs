@@ -0,0 +1,108 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use self::error::Result;
+use crate::connections::ConnectionScope;
+use crate::models::channel::Channel;
+use crate::models::key::Key;
+use crate::models::user::{Permission, User};
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state).route("/", get(count_connections))
+}
+
+/// The current number of open subscriptions, for each key, channel and client IP that has some.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionCounts {
+    keys: HashMap<Uuid, usize>,
+    channels: HashMap<Uuid, usize>,
+    ips: HashMap<IpAddr, usize>,
+    /// The anonymous subscriptions to public channels.
+    anonymous_channels: HashMap<Uuid, usize>,
+}
+
+/// Count the open subscriptions of the channels and keys the user can see.
+///
+/// Client IPs are only shown to users who can manage users in all projects.
+#[instrument]
+async fn count_connections(
+    State(state): State<SharedState>,
+    user: User,
+) -> Result<Json<ConnectionCounts>> {
+    user.require(Permission::ViewChannels)?;
+    let pool = state.read().await.pool.clone();
+    let channel_ids: HashSet<Uuid> = Channel::get_all(&pool, &user, None)
+        .await?
+        .into_iter()
+        .map(|channel| channel.id)
+        .collect();
+    let key_ids: HashSet<Uuid> = if user.require(Permission::ViewKeys).is_ok() {
+        Key::get_all(&pool, &user, None, None)
+            .await?
+            .into_iter()
+            .map(|key| key.id)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let show_ips = user.require(Permission::ManageUsers).is_ok() && user.project_id.is_none();
+    let mut counts = ConnectionCounts::default();
+    for (scope, count) in state.read().await.connections.counts() {
+        match scope {
+            ConnectionScope::Key(id) if key_ids.contains(&id) => {
+                counts.keys.insert(id, count);
+            }
+            ConnectionScope::Channel(id) if channel_ids.contains(&id) => {
+                counts.channels.insert(id, count);
+            }
+            ConnectionScope::Ip(ip) if show_ips => {
+                counts.ips.insert(ip, count);
+            }
+            ConnectionScope::AnonymousChannel(id) if channel_ids.contains(&id) => {
+                counts.anonymous_channels.insert(id, count);
+            }
+            _ => {}
+        }
+    }
+    Ok(Json(counts))
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use tracing::debug;
+
+    use crate::models::{channel, key, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        ChannelError(#[from] channel::error::Error),
+        #[error(transparent)]
+        KeyError(#[from] key::error::Error),
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::ChannelError(error) => error.into_response(),
+                Error::KeyError(error) => error.into_response(),
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+
+use crate::config::CONFIG;
+
+/// The IP address of the client.
+///
+/// If `trust_proxy` is set, this is the address added to the X-Forwarded-For header by the first
+/// of the `trusted_proxy_hops` proxies in front of the server. The addresses before it are set by
+/// the client and cannot be trusted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientIp(pub(crate) IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Infallible> {
+        if CONFIG.trust_proxy {
+            if let Some(ip) = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| forwarded_ip(value, CONFIG.trusted_proxy_hops))
+            {
+                return Ok(Self(ip));
+            }
+        }
+        let connect_info = Option::<ConnectInfo<SocketAddr>>::from_request_parts(parts, state)
+            .await
+            .expect("infallible");
+        Ok(Self(connect_info.map_or(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            |ConnectInfo(address)| address.ip(),
+        )))
+    }
+}
+
+/// Get the address added to an X-Forwarded-For header by the first of `hops` trusted proxies,
+/// counting from the right.
+fn forwarded_ip(header: &str, hops: usize) -> Option<IpAddr> {
+    header
+        .rsplit(',')
+        .nth(hops.max(1) - 1)
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_ip_ignores_spoofed_leading_entries() {
+        assert_eq!(
+            forwarded_ip("6.6.6.6, 1.2.3.4", 1),
+            Some("1.2.3.4".parse().unwrap())
+        );
+        assert_eq!(
+            forwarded_ip("6.6.6.6, 1.2.3.4, 10.0.0.1", 2),
+            Some("1.2.3.4".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn forwarded_ip_without_enough_entries() {
+        assert_eq!(forwarded_ip("1.2.3.4", 2), None);
+        assert_eq!(forwarded_ip("not an ip", 1), None);
+    }
+}
@@ -0,0 +1,129 @@
+use axum::extract::{FromRef, FromRequestParts, Query};
+use axum::headers::authorization::{Basic, Bearer};
+use axum::headers::{Authorization, Cookie};
+use axum::http::request::Parts;
+use axum::http::Method;
+use axum::{async_trait, TypedHeader};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::api::extract::client_ip::ClientIp;
+use crate::api::extract::origin::RequestOrigin;
+use crate::models::key::error::{Error, Result};
+use crate::models::key::{Key, Secret};
+use crate::state::SharedState;
+
+/// Name of the query parameter that can carry a key token on `GET` requests.
+pub(crate) const TOKEN_QUERY_PARAMETER: &str = "token";
+
+/// Name of the cookie that can carry a key token on `GET` requests.
+const TOKEN_COOKIE: &str = "mercury_token";
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Get a token from the Authorization Bearer header.
+async fn bearer_token_from_request_parts<S>(parts: &mut Parts, state: &S) -> Option<String>
+where
+    S: Send + Sync,
+{
+    TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+        .await
+        .ok()
+        .map(|TypedHeader(Authorization(bearer))| bearer.token().to_owned())
+}
+
+/// Get a subscriber's token from the Authorization Bearer header or, on `GET` requests, from the
+/// query string or from a cookie.
+///
+/// Browsers' native `EventSource` cannot set an Authorization header, so subscribers may pass their
+/// token in the `token` query parameter or in the `mercury_token` cookie instead. Only the
+/// subscription routes accept them there, so that key secrets do not end up in the access logs and
+/// the Referer headers of other requests.
+pub(crate) async fn subscriber_token_from_request_parts<S>(
+    parts: &mut Parts,
+    state: &S,
+) -> Result<Option<String>>
+where
+    S: Send + Sync,
+{
+    if let Some(token) = bearer_token_from_request_parts(parts, state).await {
+        return Ok(Some(token));
+    }
+    if parts.method != Method::GET {
+        return Ok(None);
+    }
+    let Query(query) = Query::<TokenQuery>::from_request_parts(parts, state).await?;
+    if query.token.is_some() {
+        return Ok(query.token);
+    }
+    let cookie = Option::<TypedHeader<Cookie>>::from_request_parts(parts, state)
+        .await
+        .expect("infallible");
+    match cookie {
+        Some(TypedHeader(cookie)) => cookie
+            .get(TOKEN_COOKIE)
+            .map(|token| {
+                percent_decode_str(token)
+                    .decode_utf8()
+                    .map(String::from)
+                    .map_err(|_| Error::MalformedToken)
+            })
+            .transpose(),
+        None => Ok(None),
+    }
+}
+
+/// Get the key of a token of the form `{id};{secret}`, checking it like the `Key` extractor.
+pub(crate) async fn key_from_token<S>(parts: &mut Parts, state: &S, token: &str) -> Result<Key>
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    let (id, secret) = Key::parse_token(token)?;
+    authenticate(parts, state, id, secret).await
+}
+
+/// Get the key, checking it against the request's origin and IP.
+async fn authenticate<S>(parts: &mut Parts, state: &S, id: Uuid, secret: Secret) -> Result<Key>
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    let state = SharedState::from_ref(state);
+    let origin = RequestOrigin::from_request_parts(parts, &state)
+        .await
+        .expect("infallible");
+    let ClientIp(ip) = ClientIp::from_request_parts(parts, &state)
+        .await
+        .expect("infallible");
+    let key = Key::authenticate(&state.read().await.pool, id, secret, origin.to_str(), ip).await?;
+    Ok(key)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Key
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    type Rejection = Error;
+
+    /// Parse the Authorization header, expecting either a Bearer token of the form `{id};{secret}`
+    /// or Basic credentials with the key's id as username and its secret as password.
+    ///
+    /// Then check that the key is valid, and that it can be used from the request's origin and IP.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        if let Some(token) = bearer_token_from_request_parts(parts, state).await {
+            return key_from_token(parts, state, &token).await;
+        }
+        let TypedHeader(Authorization(basic)) =
+            TypedHeader::<Authorization<Basic>>::from_request_parts(parts, state).await?;
+        let id = Key::parse_id(basic.username())?;
+        let secret = Key::parse_secret(basic.password())?;
+        authenticate(parts, state, id, secret).await
+    }
+}
@@ -1 +1,4 @@
+pub(crate) mod client_ip;
+pub(crate) mod key;
+pub(crate) mod origin;
 pub(crate) mod validated_json;
@@ -0,0 +1,29 @@
+use std::convert::Infallible;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::ORIGIN;
+use axum::http::request::Parts;
+use axum::http::HeaderValue;
+
+/// The Origin header of the request, sent by browsers on cross-origin requests.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestOrigin(pub(crate) Option<HeaderValue>);
+
+impl RequestOrigin {
+    pub(crate) fn to_str(&self) -> Option<&str> {
+        self.0.as_ref().and_then(|origin| origin.to_str().ok())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestOrigin
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(Self(parts.headers.get(ORIGIN).cloned()))
+    }
+}
@@ -1,5 +1,6 @@
 use axum::body::HttpBody;
 use axum::extract::FromRequest;
+use axum::http::header::CONTENT_TYPE;
 use axum::{async_trait, BoxError, Json};
 use hyper::Request;
 use serde::de::DeserializeOwned;
@@ -27,6 +28,30 @@ where
     }
 }
 
+/// Like `ValidatedJson`, but with the default value for requests without a body, which have no
+/// `Content-Type` header.
+pub(crate) struct ValidatedJsonOrDefault<T>(pub(crate) T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJsonOrDefault<T>
+where
+    T: DeserializeOwned + Validate + Default,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self> {
+        if !req.headers().contains_key(CONTENT_TYPE) {
+            return Ok(Self(T::default()));
+        }
+        let ValidatedJson(value) = ValidatedJson::from_request(req, state).await?;
+        Ok(Self(value))
+    }
+}
+
 mod error {
     use axum::extract::rejection::JsonRejection;
     use axum::response::IntoResponse;
@@ -0,0 +1,210 @@
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use self::error::{Error, Result};
+use crate::api::extract::validated_json::ValidatedJson;
+use crate::config::CONFIG;
+use crate::models::invitation::Invitation;
+use crate::models::project::Project;
+use crate::models::user::{self, Permission, Role, User};
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state)
+        .route("/", get(list_invitations).post(create_invitation))
+        .route("/redeem", post(redeem_invitation))
+        .route("/:id", delete(revoke_invitation))
+}
+
+/// Get all pending invitations to join the user's subtree.
+#[instrument]
+async fn list_invitations(
+    State(state): State<SharedState>,
+    user: User,
+) -> Result<Json<Vec<Invitation>>> {
+    user.require(Permission::ManageUsers)?;
+    Ok(Json(
+        Invitation::get_all(&state.read().await.pool, &user).await?,
+    ))
+}
+
+/// Validate the expiry date, which must be in the future and at most
+/// `invitation_max_lifetime_hours` hours from now (for the validator crate).
+fn validate_expires_at(expires_at: &DateTime<Utc>) -> std::result::Result<(), ValidationError> {
+    let now = Utc::now();
+    if *expires_at > now
+        && *expires_at <= now + Duration::hours(CONFIG.invitation_max_lifetime_hours)
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Invalid expiry date"))
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct CreateInvitationBody {
+    role: Role,
+    /// Defaults to self.
+    parent_id: Option<Uuid>,
+    /// Defaults to the parent's project.
+    project_id: Option<Uuid>,
+    /// Defaults to `invitation_lifetime_hours` hours from now.
+    #[validate(custom = "validate_expires_at")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateInvitationResponse {
+    #[serde(flatten)]
+    invitation: Invitation,
+    token: String,
+}
+
+/// Invite a user, returning the token they can redeem to choose their name and password.
+///
+/// The invitee will be a child of self or of a descendant user. The token will not be returned
+/// again.
+#[instrument]
+async fn create_invitation(
+    State(state): State<SharedState>,
+    user: User,
+    ValidatedJson(body): ValidatedJson<CreateInvitationBody>,
+) -> Result<(StatusCode, Json<CreateInvitationResponse>)> {
+    user.require(Permission::ManageUsers)?;
+    if !user.role.can_assign(body.role) {
+        return Err(Error::ForbiddenRole);
+    }
+    let other_parent = match body.parent_id {
+        Some(parent_id) => Some(User::get(&state.read().await.pool, parent_id).await?),
+        None => None,
+    };
+    let parent = other_parent.as_ref().unwrap_or(&user);
+    if !user
+        .is_ancestor_of(&state.read().await.pool, parent)
+        .await?
+    {
+        return Err(Error::NotDescendant);
+    }
+    // the invitee would otherwise have a higher role than their parent
+    if !parent.role.can_assign(body.role) {
+        return Err(Error::ForbiddenRole);
+    }
+    let project_id = match body.project_id {
+        Some(project_id) => Some(
+            Project::get(&state.read().await.pool, user.project(Some(project_id))?)
+                .await?
+                .id,
+        ),
+        None => parent.project_id,
+    };
+    if !project_id.map_or(parent.project_id.is_none(), |id| parent.can_access(id)) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    let expires_at = body
+        .expires_at
+        .unwrap_or_else(|| Utc::now() + Duration::hours(CONFIG.invitation_lifetime_hours));
+    let (invitation, token) = Invitation::new(
+        &state.read().await.pool,
+        parent,
+        body.role,
+        project_id,
+        expires_at,
+    )
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateInvitationResponse { invitation, token }),
+    ))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct RedeemInvitationBody {
+    token: String,
+    #[validate(custom = "user::validate_name")]
+    name: String,
+    #[validate(length(min = 8))]
+    password: String,
+}
+
+/// Create the invited user, with their chosen name and password.
+///
+/// Does not require authentication: the invitation token is enough.
+#[instrument(skip(body))]
+async fn redeem_invitation(
+    State(state): State<SharedState>,
+    ValidatedJson(body): ValidatedJson<RedeemInvitationBody>,
+) -> Result<(StatusCode, Json<User>)> {
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            Invitation::redeem(
+                &state.read().await.pool,
+                &body.token,
+                &body.name,
+                &body.password,
+            )
+            .await?,
+        ),
+    ))
+}
+
+/// Revoke a pending invitation.
+#[instrument]
+async fn revoke_invitation(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageUsers)?;
+    let invitation = Invitation::get(&state.read().await.pool, &user, id).await?;
+    invitation.delete(&state.read().await.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::debug;
+
+    use crate::models::{invitation, project, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        ProjectError(#[from] project::error::Error),
+        #[error(transparent)]
+        InvitationError(#[from] invitation::error::Error),
+        #[error("Can only invite users under self or descendant users")]
+        NotDescendant,
+        #[error("Cannot assign this role")]
+        ForbiddenRole,
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::ProjectError(error) => error.into_response(),
+                Error::InvitationError(error) => error.into_response(),
+                Error::NotDescendant => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+                Error::ForbiddenRole => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            }
+        }
+    }
+}
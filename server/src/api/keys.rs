@@ -1,44 +1,188 @@
-use axum::extract::{Path, State};
-use axum::routing::get;
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post, put};
 use axum::{Json, Router};
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
+use ipnetwork::IpNetwork;
 use serde::Deserialize;
 use tracing::instrument;
 use uuid::Uuid;
+use validator::{Validate, ValidationError};
 
-use self::error::Result;
+use self::error::{Error, Result};
+use crate::api::extract::validated_json::{ValidatedJson, ValidatedJsonOrDefault};
 use crate::models::channel::Channel;
-use crate::models::key::{Key, KeyType};
-use crate::models::user::User;
+use crate::models::key::{
+    Capability, Grant, Grants, Key, KeyAllowlists, KeyLimits, KeyMetadata, PatternGrant,
+};
+use crate::models::project::Project;
+use crate::models::user::{self, Permission, User};
 use crate::state::SharedState;
 
 pub(crate) fn app(state: SharedState) -> Router<SharedState> {
     Router::with_state(state)
         .route("/", get(list_keys).post(create_key))
-        .route("/:id", get(list_channels).delete(delete_key))
+        .route(
+            "/:id",
+            get(list_channels).patch(update_grants).delete(delete_key),
+        )
+        .route("/:id/grants", get(list_grants))
+        .route("/:id/rotate", post(rotate_key))
+        .route("/:id/limits", put(set_limits))
+        .route("/:id/allowlists", put(set_allowlists))
+        .route("/:id/collaborators", get(list_collaborators))
+        .route(
+            "/:id/collaborators/:user_id",
+            put(add_collaborator).delete(remove_collaborator),
+        )
 }
 
-/// Get all keys.
+#[derive(Debug, Deserialize)]
+struct ListKeysQuery {
+    project: Option<Uuid>,
+    label: Option<String>,
+}
+
+/// Get all keys that the user can see, optionally only those of a project or with a label.
 #[instrument]
-async fn list_keys(State(state): State<SharedState>, user: User) -> Result<Json<Vec<Key>>> {
-    Ok(Json(Key::get_all(&state.read().await.pool).await?))
+async fn list_keys(
+    State(state): State<SharedState>,
+    user: User,
+    Query(query): Query<ListKeysQuery>,
+) -> Result<Json<Vec<Key>>> {
+    user.require(Permission::ViewKeys)?;
+    Ok(Json(
+        Key::get_all(
+            &state.read().await.pool,
+            &user,
+            query.project,
+            query.label.as_deref(),
+        )
+        .await?,
+    ))
 }
 
-#[derive(Debug, Deserialize)]
+/// Validate the labels (for the validator crate).
+fn validate_labels(labels: &[String]) -> std::result::Result<(), ValidationError> {
+    if labels
+        .iter()
+        .all(|label| !label.is_empty() && label.len() <= 32)
+    {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Invalid label"))
+    }
+}
+
+/// Validate the origins, which must have no path (for the validator crate).
+fn validate_origins(origins: &[String]) -> std::result::Result<(), ValidationError> {
+    if origins.iter().all(|origin| {
+        origin.len() <= 256 && validator::validate_url(origin) && origin.matches('/').count() == 2
+    }) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Invalid origin"))
+    }
+}
+
+/// Where a key can be used from, anywhere by default.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct AllowlistsBody {
+    /// Origins such as `https://example.com`.
+    #[serde(default)]
+    #[validate(custom = "validate_origins")]
+    allowed_origins: Vec<String>,
+    /// IP ranges in CIDR notation.
+    #[serde(default)]
+    allowed_ips: Vec<IpNetwork>,
+}
+
+impl From<AllowlistsBody> for KeyAllowlists {
+    fn from(body: AllowlistsBody) -> Self {
+        Self {
+            allowed_origins: body.allowed_origins,
+            allowed_ips: body.allowed_ips,
+        }
+    }
+}
+
+/// The publish rate limits and quotas of a key, none by default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LimitsBody {
+    /// Messages per second.
+    publish_rate_limit: Option<f64>,
+    /// Bytes per second.
+    publish_byte_rate_limit: Option<f64>,
+    /// Messages per day.
+    daily_quota: Option<i64>,
+    /// Messages per month.
+    monthly_quota: Option<i64>,
+}
+
+impl From<LimitsBody> for KeyLimits {
+    fn from(body: LimitsBody) -> Self {
+        Self {
+            publish_rate_limit: body.publish_rate_limit,
+            publish_byte_rate_limit: body.publish_byte_rate_limit,
+            daily_quota: body.daily_quota,
+            monthly_quota: body.monthly_quota,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
 struct CreateKeyBody {
-    r#type: KeyType,
+    /// Defaults to the user's project.
+    project_id: Option<Uuid>,
+    #[validate(length(min = 1, max = 64))]
+    name: Option<String>,
+    #[validate(length(max = 1024))]
+    description: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_labels")]
+    labels: Vec<String>,
+    /// The capabilities the key holds, granted on all its channels.
+    capabilities: Vec<Capability>,
     channels: Vec<Uuid>,
+    not_before: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    limits: LimitsBody,
+    #[serde(default)]
+    #[validate]
+    allowlists: AllowlistsBody,
 }
 
-/// Create a key.
+/// Create a key in a project.
 #[instrument]
 async fn create_key(
     State(state): State<SharedState>,
     user: User,
-    Json(body): Json<CreateKeyBody>,
+    ValidatedJson(body): ValidatedJson<CreateKeyBody>,
 ) -> Result<String> {
+    user.require(Permission::ManageKeys)?;
+    let project = Project::get(&state.read().await.pool, user.project(body.project_id)?).await?;
     // TODO: next 2 instructions in 1 method
-    let (key, secret) = Key::new(&state.read().await.pool, body.r#type, body.channels).await?;
+    let (key, secret) = Key::new(
+        &state.read().await.pool,
+        &user,
+        project.id,
+        KeyMetadata {
+            name: body.name,
+            description: body.description,
+            labels: body.labels,
+        },
+        body.limits.into(),
+        body.allowlists.into(),
+        body.capabilities,
+        body.channels,
+        body.not_before,
+        body.expires_at,
+    )
+    .await?;
     Ok(format!("{};{}", key.id, secret.as_ref()))
 }
 
@@ -49,12 +193,157 @@ async fn list_channels(
     user: User,
     Path(id): Path<Uuid>,
 ) -> Result<Json<Vec<Channel>>> {
-    let key = Key::get(&state.read().await.pool, id).await?;
+    user.require(Permission::ViewChannels)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    Ok(Json(
+        Channel::get_from_key(&state.read().await.pool, &key, &user).await?,
+    ))
+}
+
+/// Get a key's capabilities on each of its channels and patterns.
+#[instrument]
+async fn list_grants(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Grants>> {
+    user.require(Permission::ViewKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    Ok(Json(key.grants(&state.read().await.pool).await?))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GrantBody {
+    channel_id: Uuid,
+    /// Defaults to all the key's capabilities.
+    capabilities: Option<Vec<Capability>>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct PatternGrantBody {
+    /// A channel name pattern, where `*` matches any sequence of characters.
+    #[validate(length(min = 1, max = 16))]
+    pattern: String,
+    /// Defaults to all the key's capabilities.
+    capabilities: Option<Vec<Capability>>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct UpdateGrantsBody {
+    #[serde(default)]
+    grant: Vec<GrantBody>,
+    #[serde(default)]
+    revoke: Vec<Uuid>,
+    #[serde(default)]
+    #[validate]
+    grant_patterns: Vec<PatternGrantBody>,
+    #[serde(default)]
+    revoke_patterns: Vec<String>,
+}
+
+/// Grant or revoke a key's capabilities on channels and patterns.
+#[instrument]
+async fn update_grants(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<UpdateGrantsBody>,
+) -> Result<Json<Grants>> {
+    user.require(Permission::ManageKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    let grants = Grants {
+        channels: body
+            .grant
+            .into_iter()
+            .map(|grant| Grant {
+                channel_id: grant.channel_id,
+                capabilities: grant
+                    .capabilities
+                    .unwrap_or_else(|| key.capabilities().to_vec()),
+            })
+            .collect(),
+        patterns: body
+            .grant_patterns
+            .into_iter()
+            .map(|grant| PatternGrant {
+                pattern: grant.pattern,
+                capabilities: grant
+                    .capabilities
+                    .unwrap_or_else(|| key.capabilities().to_vec()),
+            })
+            .collect(),
+    };
     Ok(Json(
-        Channel::get_from_key(&state.read().await.pool, &key).await?,
+        key.update_grants(
+            &state.read().await.pool,
+            &user,
+            &grants,
+            &body.revoke,
+            &body.revoke_patterns,
+        )
+        .await?,
     ))
 }
 
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct RotateKeyBody {
+    /// How long the old secret stays valid, in seconds.
+    #[validate(range(min = 1, max = 604800))]
+    grace_period: Option<i32>,
+}
+
+/// Replace a key's secret, keeping its id and its channels.
+///
+/// The body can be left out, to invalidate the old secret right away.
+#[instrument]
+async fn rotate_key(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJsonOrDefault(body): ValidatedJsonOrDefault<RotateKeyBody>,
+) -> Result<String> {
+    user.require(Permission::ManageKeys)?;
+    let mut key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    let secret = key
+        .rotate(&state.read().await.pool, body.grace_period)
+        .await?;
+    Ok(format!("{};{}", key.id, secret.as_ref()))
+}
+
+/// Replace a key's publish rate limits and quotas.
+#[instrument]
+async fn set_limits(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    Json(body): Json<LimitsBody>,
+) -> Result<Json<Key>> {
+    user.require(Permission::ManageKeys)?;
+    let mut key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    key.set_limits(&state.read().await.pool, body.into())
+        .await?;
+    Ok(Json(key))
+}
+
+/// Replace the origins and IP ranges a key can be used from.
+#[instrument]
+async fn set_allowlists(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<AllowlistsBody>,
+) -> Result<Json<Key>> {
+    user.require(Permission::ManageKeys)?;
+    let mut key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    key.set_allowlists(&state.read().await.pool, body.into())
+        .await?;
+    Ok(Json(key))
+}
+
 /// Delete a key.
 #[instrument]
 async fn delete_key(
@@ -62,33 +351,95 @@ async fn delete_key(
     user: User,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode> {
-    let key = Key::get(&state.read().await.pool, id).await?;
+    user.require(Permission::ManageKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
     key.delete(&state.read().await.pool).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Get the ids of the users a key is shared with.
+#[instrument]
+async fn list_collaborators(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Uuid>>> {
+    user.require(Permission::ViewKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    Ok(Json(key.collaborators(&state.read().await.pool).await?))
+}
+
+/// Share a key with a user.
+///
+/// Only the user who created the key and their ancestors in the user hierarchy can share it.
+#[instrument]
+async fn add_collaborator(
+    State(state): State<SharedState>,
+    user: User,
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    if !key.is_managed_by(&state.read().await.pool, &user).await? {
+        return Err(Error::NotManager);
+    }
+    let collaborator = User::get(&state.read().await.pool, user_id).await?;
+    if !collaborator.can_access(key.project_id) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    key.share(&state.read().await.pool, &collaborator).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stop sharing a key with a user.
+#[instrument]
+async fn remove_collaborator(
+    State(state): State<SharedState>,
+    user: User,
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageKeys)?;
+    let key = Key::get_visible(&state.read().await.pool, id, &user).await?;
+    if !key.is_managed_by(&state.read().await.pool, &user).await? {
+        return Err(Error::NotManager);
+    }
+    key.unshare(&state.read().await.pool, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 mod error {
     use axum::response::IntoResponse;
+    use hyper::StatusCode;
     use tracing::debug;
 
-    use crate::models::{channel, key};
+    use crate::models::{channel, key, project, user};
 
     pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+    #[allow(clippy::enum_variant_names)]
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
         #[error(transparent)]
         KeyError(#[from] key::error::Error),
         #[error(transparent)]
         ChannelError(#[from] channel::error::Error),
+        #[error(transparent)]
+        ProjectError(#[from] project::error::Error),
+        #[error("Only the creator of the key and their ancestors can share it")]
+        NotManager,
     }
 
     impl IntoResponse for Error {
         fn into_response(self) -> axum::response::Response {
             debug!(?self);
             match self {
+                Error::UserError(error) => error.into_response(),
                 Error::KeyError(error) => error.into_response(),
                 Error::ChannelError(error) => error.into_response(),
+                Error::ProjectError(error) => error.into_response(),
+                Error::NotManager => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
             }
         }
     }
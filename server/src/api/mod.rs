@@ -1,6 +1,12 @@
+pub(crate) mod access_tokens;
+pub(crate) mod auth;
 pub(crate) mod channels;
+pub(crate) mod connections;
 pub(crate) mod extract;
+pub(crate) mod invitations;
 pub(crate) mod keys;
+pub(crate) mod projects;
+pub(crate) mod tokens;
 pub(crate) mod users;
 
 use std::sync::Arc;
@@ -11,7 +17,13 @@ use crate::state::SharedState;
 
 pub(crate) fn app(state: SharedState) -> Router<SharedState> {
     Router::with_state(Arc::clone(&state))
+        .nest("/auth", auth::app(Arc::clone(&state)))
+        .nest("/projects", projects::app(Arc::clone(&state)))
         .nest("/users", users::app(Arc::clone(&state)))
+        .nest("/invitations", invitations::app(Arc::clone(&state)))
+        .nest("/access-tokens", access_tokens::app(Arc::clone(&state)))
         .nest("/channels", channels::app(Arc::clone(&state)))
         .nest("/keys", keys::app(Arc::clone(&state)))
+        .nest("/tokens", tokens::app(Arc::clone(&state)))
+        .nest("/connections", connections::app(Arc::clone(&state)))
 }
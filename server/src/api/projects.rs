@@ -0,0 +1,105 @@
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use hyper::StatusCode;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+use self::error::{Error, Result};
+use crate::api::extract::validated_json::ValidatedJson;
+use crate::models::project::Project;
+use crate::models::user::{Permission, User};
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state)
+        .route("/", get(list_projects).post(create_project))
+        .route("/:id", delete(delete_project))
+}
+
+/// Get all projects, or only the user's own if they belong to one.
+#[instrument]
+async fn list_projects(State(state): State<SharedState>, user: User) -> Result<Json<Vec<Project>>> {
+    match user.project_id {
+        Some(project_id) => Ok(Json(vec![
+            Project::get(&state.read().await.pool, project_id).await?,
+        ])),
+        None => Ok(Json(Project::get_all(&state.read().await.pool).await?)),
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct CreateProjectBody {
+    #[validate(length(min = 4, max = 16))]
+    name: String,
+}
+
+/// Create a project.
+///
+/// Only users who can access all projects can create and delete projects.
+#[instrument]
+async fn create_project(
+    State(state): State<SharedState>,
+    user: User,
+    ValidatedJson(body): ValidatedJson<CreateProjectBody>,
+) -> Result<(StatusCode, Json<Project>)> {
+    user.require(Permission::ManageUsers)?;
+    if user.project_id.is_some() {
+        return Err(Error::ProjectUser);
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(Project::new(&state.read().await.pool, &body.name).await?),
+    ))
+}
+
+/// Delete a project, with all its channels, keys and users.
+#[instrument]
+async fn delete_project(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageUsers)?;
+    if user.project_id.is_some() {
+        return Err(Error::ProjectUser);
+    }
+    let project = Project::get(&state.read().await.pool, id).await?;
+    project.delete(&state.read().await.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::debug;
+
+    use crate::models::{project, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        ProjectError(#[from] project::error::Error),
+        #[error("Only users who can access all projects can manage projects")]
+        ProjectUser,
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::ProjectError(error) => error.into_response(),
+                Error::ProjectUser => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            }
+        }
+    }
+}
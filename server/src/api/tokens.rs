@@ -0,0 +1,159 @@
+use axum::extract::{FromRef, FromRequestParts, State};
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::http::request::Parts;
+use axum::routing::post;
+use axum::{async_trait, Router, TypedHeader};
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+use self::error::{Error, Result};
+use crate::api::extract::validated_json::ValidatedJson;
+use crate::models::channel::Channel;
+use crate::models::key::{Capability, Key};
+use crate::models::token::Token;
+use crate::models::user::{Permission, User};
+use crate::state::SharedState;
+
+pub(crate) fn app(state: SharedState) -> Router<SharedState> {
+    Router::with_state(state).route("/", post(create_token))
+}
+
+/// Who is allowed to issue tokens: a user, or a key with the publish capability.
+#[derive(Debug)]
+enum Issuer {
+    User(User),
+    Key(Box<Key>),
+}
+
+impl Issuer {
+    /// Identify the issuer in the token, as `user:{id}` or `key:{id}`.
+    fn to_claim(&self) -> String {
+        match self {
+            Self::User(user) => format!("user:{}", user.id),
+            Self::Key(key) => format!("key:{}", key.id),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Issuer
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    type Rejection = Error;
+
+    /// Use the `Key` extractor for Bearer key tokens, and the `User` extractor otherwise.
+    ///
+    /// Users need the permission to manage keys.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        if TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+            .await
+            .is_ok_and(|TypedHeader(Authorization(bearer))| bearer.token().contains(';'))
+        {
+            Ok(Self::Key(Box::new(
+                Key::from_request_parts(parts, state).await?,
+            )))
+        } else {
+            let user = User::from_request_parts(parts, state).await?;
+            user.require(Permission::ManageKeys)?;
+            Ok(Self::User(user))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct CreateTokenBody {
+    #[validate(length(min = 1, max = 256))]
+    subject: String,
+    channels: Vec<Uuid>,
+    /// Lifetime of the token, in seconds.
+    #[validate(range(min = 1, max = 86400))]
+    expires_in: u64,
+}
+
+/// Create a short-lived subscriber token.
+///
+/// A key can only create tokens for the channels it can publish on, and a user for the channels
+/// they can see. All the channels must be in the same project.
+#[instrument]
+async fn create_token(
+    State(state): State<SharedState>,
+    issuer: Issuer,
+    ValidatedJson(body): ValidatedJson<CreateTokenBody>,
+) -> Result<String> {
+    let mut project = None;
+    for &channel_id in &body.channels {
+        let channel = match &issuer {
+            Issuer::User(user) => {
+                Channel::get_visible(&state.read().await.pool, channel_id, user).await?
+            }
+            Issuer::Key(key) => {
+                let channel = Channel::get(&state.read().await.pool, channel_id).await?;
+                if !key
+                    .authorizes(&state.read().await.pool, &channel, Capability::Publish)
+                    .await?
+                {
+                    return Err(Error::UnauthorizedChannel);
+                }
+                channel
+            }
+        };
+        match project {
+            Some(project) if project != channel.project_id => return Err(Error::MixedProjects),
+            _ => project = Some(channel.project_id),
+        }
+    }
+    let token = Token::new(
+        issuer.to_claim(),
+        body.subject,
+        project,
+        body.channels,
+        body.expires_in,
+    );
+    Ok(token.sign(&state.read().await.token_keys))
+}
+
+mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::debug;
+
+    use crate::models::{channel, key, user};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error(transparent)]
+        KeyError(#[from] key::error::Error),
+        #[error(transparent)]
+        ChannelError(#[from] channel::error::Error),
+        #[error("Unauthorized channel")]
+        UnauthorizedChannel,
+        #[error("Channels from different projects")]
+        MixedProjects,
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::KeyError(error) => error.into_response(),
+                Error::ChannelError(error) => error.into_response(),
+                Error::UnauthorizedChannel => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+                Error::MixedProjects => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            }
+        }
+    }
+}
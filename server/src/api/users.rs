@@ -1,15 +1,18 @@
-use axum::extract::{Path, State};
-use axum::routing::{delete, get, patch};
+use axum::extract::{Path, Query, State};
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
 use hyper::StatusCode;
 use serde::Deserialize;
+use sqlx::PgPool;
 use tracing::instrument;
 use uuid::Uuid;
 use validator::Validate;
 
 use self::error::{Error, Result};
 use crate::api::extract::validated_json::ValidatedJson;
-use crate::models::user::User;
+use crate::models::project::Project;
+use crate::models::session::Session;
+use crate::models::user::{self, AnyUser, Permission, Role, TotpEnrolment, User};
 use crate::state::SharedState;
 
 pub(crate) fn app(state: SharedState) -> Router<SharedState> {
@@ -17,36 +20,64 @@ pub(crate) fn app(state: SharedState) -> Router<SharedState> {
         .route("/", get(list_users).post(create_user))
         .route("/rename", patch(rename))
         .route("/change-password", patch(change_password))
+        .route("/totp", post(start_totp_enrolment).delete(disable_totp))
+        .route("/totp/confirm", post(confirm_totp_enrolment))
+        .route("/totp/recovery-codes", post(new_recovery_codes))
         .route("/:id", delete(delete_user))
+        .route("/:id/role", patch(set_role))
+        .route("/:id/parent", patch(set_parent))
+        .route("/:id/totp", delete(disable_other_totp))
 }
 
-/// Get self and all lower ranked users.
+/// Get self and all descendant users.
 #[instrument]
 async fn list_users(State(state): State<SharedState>, user: User) -> Result<Json<Vec<User>>> {
-    let min_rank = user.rank + 1;
+    user.require(Permission::ViewUsers)?;
+    let mut descendants = User::get_descendants(&state.read().await.pool, &user).await?;
     let mut users = vec![user];
-    users.append(&mut User::get_all(&state.read().await.pool, min_rank).await?);
+    users.append(&mut descendants);
     Ok(Json(users))
 }
 
 #[derive(Debug, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 struct CreateUserBody {
-    #[validate(length(min = 4, max = 16))]
+    #[validate(custom = "user::validate_name")]
     name: String,
     #[validate(length(min = 8))]
     password: String,
+    #[serde(default = "default_role")]
+    role: Role,
+    /// Defaults to the user's project. Users who can access all projects can create users that
+    /// can too, by leaving it out.
+    project_id: Option<Uuid>,
+}
+
+fn default_role() -> Role {
+    Role::Viewer
 }
 
 /// Create a user.
 ///
-/// The new user's rank will be equal to its parent user's rank + 1.
+/// The new user will be a child of the user creating them, and will belong to the same project.
 #[instrument]
 async fn create_user(
     State(state): State<SharedState>,
     user: User,
     ValidatedJson(body): ValidatedJson<CreateUserBody>,
 ) -> Result<(StatusCode, Json<User>)> {
+    user.require(Permission::ManageUsers)?;
+    if !user.role.can_assign(body.role) {
+        return Err(Error::ForbiddenRole);
+    }
+    let project_id = match body.project_id {
+        Some(project_id) => Some(
+            Project::get(&state.read().await.pool, user.project(Some(project_id))?)
+                .await?
+                .id,
+        ),
+        None => user.project_id,
+    };
     Ok((
         StatusCode::CREATED,
         Json(
@@ -54,7 +85,9 @@ async fn create_user(
                 &state.read().await.pool,
                 &body.name,
                 &body.password,
-                user.rank + 1,
+                &user,
+                body.role,
+                project_id,
             )
             .await?,
         ),
@@ -64,7 +97,7 @@ async fn create_user(
 #[derive(Debug, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
 struct RenameBody {
-    #[validate(length(min = 4, max = 16))]
+    #[validate(custom = "user::validate_name")]
     name: String,
 }
 
@@ -75,6 +108,7 @@ async fn rename(
     mut user: User,
     ValidatedJson(body): ValidatedJson<RenameBody>,
 ) -> Result<Json<User>> {
+    user.require(Permission::ManageAccount)?;
     user.rename(&state.read().await.pool, &body.name).await?;
     Ok(Json(user))
 }
@@ -86,32 +120,235 @@ struct ChangePasswordBody {
     password: String,
 }
 
-/// Change own password.
+/// Change own password, and close all other sessions.
+///
+/// This is the only endpoint available to users who must change their password.
 #[instrument]
 async fn change_password(
     State(state): State<SharedState>,
-    mut user: User,
+    AnyUser(mut user): AnyUser,
+    session: Option<Session>,
     ValidatedJson(body): ValidatedJson<ChangePasswordBody>,
 ) -> Result<Json<User>> {
+    user.require(Permission::ManageAccount)?;
     user.change_password(&state.read().await.pool, &body.password)
         .await?;
+    Session::delete_others(
+        &state.read().await.pool,
+        user.id,
+        session.map(|session| session.id),
+    )
+    .await?;
     Ok(Json(user))
 }
 
-/// Delete a user.
+/// Start enrolling TOTP, returning the secret to add to an authenticator app.
+#[instrument]
+async fn start_totp_enrolment(
+    State(state): State<SharedState>,
+    mut user: User,
+) -> Result<Json<TotpEnrolment>> {
+    user.require(Permission::ManageAccount)?;
+    Ok(Json(
+        user.start_totp_enrolment(&state.read().await.pool).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct ConfirmTotpBody {
+    #[validate(length(equal = 6))]
+    code: String,
+}
+
+/// Confirm the TOTP enrolment with a code from the authenticator app, returning recovery codes.
+#[instrument(skip(body))]
+async fn confirm_totp_enrolment(
+    State(state): State<SharedState>,
+    mut user: User,
+    ValidatedJson(body): ValidatedJson<ConfirmTotpBody>,
+) -> Result<Json<Vec<String>>> {
+    user.require(Permission::ManageAccount)?;
+    Ok(Json(
+        user.confirm_totp_enrolment(&state.read().await.pool, &body.code)
+            .await?,
+    ))
+}
+
+/// Replace own recovery codes with new ones.
+#[instrument]
+async fn new_recovery_codes(
+    State(state): State<SharedState>,
+    user: User,
+) -> Result<Json<Vec<String>>> {
+    user.require(Permission::ManageAccount)?;
+    Ok(Json(
+        user.new_recovery_codes(&state.read().await.pool).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct DisableTotpBody {
+    /// A TOTP code or an unused recovery code.
+    code: String,
+}
+
+/// Stop requiring TOTP codes for self, given the second factor.
+#[instrument(skip(body))]
+async fn disable_totp(
+    State(state): State<SharedState>,
+    mut user: User,
+    ValidatedJson(body): ValidatedJson<DisableTotpBody>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageAccount)?;
+    user.check_second_factor(&state.read().await.pool, Some(&body.code))
+        .await?;
+    user.disable_totp(&state.read().await.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stop requiring TOTP codes for a descendant user, who lost their authenticator and their
+/// recovery codes.
+#[instrument]
+async fn disable_other_totp(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    user.require(Permission::ManageUsers)?;
+    let mut other_user = User::get(&state.read().await.pool, id).await?;
+    if !user.reaches(&other_user) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    if !user.manages(&state.read().await.pool, &other_user).await? {
+        return Err(Error::NotDescendant);
+    }
+    if !user.role.can_assign(other_user.role) {
+        return Err(Error::ForbiddenRole);
+    }
+    other_user.disable_totp(&state.read().await.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeleteUserQuery {
+    /// Defaults to the deleted user's parent.
+    heir: Option<Uuid>,
+}
+
+/// Delete a descendant user.
+///
+/// Their children and the channels and keys they created go to the heir, who must be self or
+/// another descendant user outside of the deleted user's subtree.
 #[instrument]
 async fn delete_user(
     State(state): State<SharedState>,
     user: User,
     Path(id): Path<Uuid>,
+    Query(query): Query<DeleteUserQuery>,
 ) -> Result<StatusCode> {
+    user.require(Permission::ManageUsers)?;
     let other_user = User::get(&state.read().await.pool, id).await?;
-    if other_user.rank > user.rank {
-        other_user.delete(&state.read().await.pool).await?;
-        Ok(StatusCode::NO_CONTENT)
-    } else {
-        Err(Error::HigherRankUser)
+    if !user.reaches(&other_user) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    if !user.manages(&state.read().await.pool, &other_user).await? {
+        return Err(Error::NotDescendant);
     }
+    let heir_id = query
+        .heir
+        .or(other_user.parent_id)
+        .expect("descendant users have a parent");
+    let heir = User::get(&state.read().await.pool, heir_id).await?;
+    check_new_parent(&state.read().await.pool, &user, &other_user, &heir).await?;
+    other_user.delete(&state.read().await.pool, &heir).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct SetParentBody {
+    parent_id: Uuid,
+}
+
+/// Move a descendant user, with their own descendants, under another parent.
+///
+/// The new parent must be self or another descendant user outside of the moved user's subtree.
+#[instrument]
+async fn set_parent(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<SetParentBody>,
+) -> Result<Json<User>> {
+    user.require(Permission::ManageUsers)?;
+    let mut other_user = User::get(&state.read().await.pool, id).await?;
+    if !user.reaches(&other_user) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    if !user.manages(&state.read().await.pool, &other_user).await? {
+        return Err(Error::NotDescendant);
+    }
+    let parent = User::get(&state.read().await.pool, body.parent_id).await?;
+    check_new_parent(&state.read().await.pool, &user, &other_user, &parent).await?;
+    other_user
+        .set_parent(&state.read().await.pool, &parent)
+        .await?;
+    Ok(Json(other_user))
+}
+
+/// Check that a user can take the place of the parent of another user's descendant: they must be
+/// that user or another of their descendants, able to reach the descendant's project, and outside
+/// of the descendant's subtree.
+async fn check_new_parent(
+    pool: &PgPool,
+    user: &User,
+    descendant: &User,
+    parent: &User,
+) -> Result<()> {
+    if !user.is_ancestor_of(pool, parent).await? {
+        return Err(Error::NotDescendant);
+    }
+    if !parent.reaches(descendant) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    if descendant.is_ancestor_of(pool, parent).await? {
+        return Err(Error::CyclicHierarchy);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct SetRoleBody {
+    role: Role,
+}
+
+/// Change the role of a descendant user.
+#[instrument]
+async fn set_role(
+    State(state): State<SharedState>,
+    user: User,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<SetRoleBody>,
+) -> Result<Json<User>> {
+    user.require(Permission::ManageUsers)?;
+    let mut other_user = User::get(&state.read().await.pool, id).await?;
+    if !user.reaches(&other_user) {
+        return Err(user::error::Error::OtherProject.into());
+    }
+    if !user.manages(&state.read().await.pool, &other_user).await? {
+        return Err(Error::NotDescendant);
+    }
+    if !user.role.can_assign(other_user.role) || !user.role.can_assign(body.role) {
+        return Err(Error::ForbiddenRole);
+    }
+    other_user
+        .set_role(&state.read().await.pool, body.role)
+        .await?;
+    Ok(Json(other_user))
 }
 
 mod error {
@@ -119,16 +356,25 @@ mod error {
     use hyper::StatusCode;
     use tracing::debug;
 
-    use crate::models::user;
+    use crate::models::{project, session, user};
 
     pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+    #[allow(clippy::enum_variant_names)]
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum Error {
         #[error(transparent)]
         UserError(#[from] user::error::Error),
-        #[error("Cannot delete a higher ranked user")]
-        HigherRankUser,
+        #[error(transparent)]
+        SessionError(#[from] session::error::Error),
+        #[error(transparent)]
+        ProjectError(#[from] project::error::Error),
+        #[error("Can only manage descendant users")]
+        NotDescendant,
+        #[error("Cannot move a user under their own subtree")]
+        CyclicHierarchy,
+        #[error("Cannot assign this role")]
+        ForbiddenRole,
     }
 
     impl IntoResponse for Error {
@@ -136,9 +382,13 @@ mod error {
             debug!(?self);
             match self {
                 Error::UserError(error) => error.into_response(),
-                Error::HigherRankUser => {
-                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                Error::SessionError(error) => error.into_response(),
+                Error::ProjectError(error) => error.into_response(),
+                Error::NotDescendant => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+                Error::CyclicHierarchy => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
                 }
+                Error::ForbiddenRole => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
             }
         }
     }
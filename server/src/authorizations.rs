@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::config::CONFIG;
+use crate::models::channel::Channel;
+use crate::state::SharedState;
+
+/// The decisions of the channels' authorization webhooks, cached for `authorization_cache_ttl`
+/// seconds.
+#[derive(Debug)]
+pub(crate) struct Authorizations {
+    client: reqwest::Client,
+    decisions: HashMap<(Uuid, Option<String>), (bool, Instant)>,
+}
+
+impl Default for Authorizations {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(CONFIG.authorization_timeout))
+                .build()
+                .expect("HTTP client"),
+            decisions: HashMap::new(),
+        }
+    }
+}
+
+/// The body of the request sent to an authorization webhook.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizationRequest<'a> {
+    channel_id: Uuid,
+    channel_name: &'a str,
+    /// The identity of the subscriber, none for anonymous subscribers.
+    subscriber: Option<&'a str>,
+}
+
+impl Authorizations {
+    /// Forget the cached decisions about a channel.
+    pub(crate) fn forget_channel(&mut self, channel_id: Uuid) {
+        self.decisions.retain(|(id, _), _| *id != channel_id);
+    }
+
+    /// Forget the expired decisions.
+    pub(crate) fn prune(&mut self) {
+        let now = Instant::now();
+        self.decisions
+            .retain(|_, (_, expires_at)| *expires_at > now);
+    }
+
+    /// The cached decision about a subscriber to a channel, unless it expired.
+    fn cached(&self, cache_key: &(Uuid, Option<String>)) -> Option<bool> {
+        self.decisions
+            .get(cache_key)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|&(allowed, _)| allowed)
+    }
+
+    /// Cache a decision for `authorization_cache_ttl` seconds.
+    fn remember(&mut self, cache_key: (Uuid, Option<String>), allowed: bool) {
+        let expires_at = Instant::now() + Duration::from_secs(CONFIG.authorization_cache_ttl);
+        self.decisions.insert(cache_key, (allowed, expires_at));
+    }
+}
+
+/// Ask an authorization webhook, which authorizes the subscriber if it responds with a 2xx status
+/// code.
+async fn ask(
+    client: &reqwest::Client,
+    url: &str,
+    request: &AuthorizationRequest<'_>,
+) -> reqwest::Result<bool> {
+    let response = client.post(url).json(request).send().await?;
+    let allowed = response.status().is_success();
+    debug!(status = %response.status(), allowed);
+    Ok(allowed)
+}
+
+/// Ask the channel's authorization webhook whether the subscriber can subscribe to the channel,
+/// unless the decision is cached.
+///
+/// The subscriber is authorized if the webhook responds with a 2xx status code.
+#[instrument(skip(state, channel), fields(channel = %channel.name))]
+pub(crate) async fn check(
+    state: &SharedState,
+    channel: &Channel,
+    url: &str,
+    subscriber: Option<String>,
+) -> reqwest::Result<bool> {
+    let cache_key = (channel.id, subscriber);
+    let client = {
+        let state = state.read().await;
+        if let Some(allowed) = state.authorizations.cached(&cache_key) {
+            return Ok(allowed);
+        }
+        state.authorizations.client.clone()
+    };
+    let allowed = ask(
+        &client,
+        url,
+        &AuthorizationRequest {
+            channel_id: channel.id,
+            channel_name: &channel.name,
+            subscriber: cache_key.1.as_deref(),
+        },
+    )
+    .await?;
+    state
+        .write()
+        .await
+        .authorizations
+        .remember(cache_key, allowed);
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use hyper::StatusCode;
+    use serde_json::Value;
+
+    use super::*;
+
+    const CHANNEL_ID: Uuid = Uuid::from_u128(1);
+
+    /// Start a webhook that allows the subscriber named `allowed` at `/`, fails at `/error` and
+    /// responds in a second at `/slow`.
+    fn spawn_webhook() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/",
+                post(|Json(body): Json<Value>| async move {
+                    if body["subscriber"] == "allowed" {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::FORBIDDEN
+                    }
+                }),
+            )
+            .route(
+                "/error",
+                post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route(
+                "/slow",
+                post(|| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    StatusCode::OK
+                }),
+            );
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        addr
+    }
+
+    async fn ask_webhook(url: &str, subscriber: Option<&str>) -> reqwest::Result<bool> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        ask(
+            &client,
+            url,
+            &AuthorizationRequest {
+                channel_id: CHANNEL_ID,
+                channel_name: "channel",
+                subscriber,
+            },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn webhooks_allow_with_2xx_only() {
+        let addr = spawn_webhook();
+        let url = format!("http://{addr}/");
+        assert!(ask_webhook(&url, Some("allowed")).await.unwrap());
+        assert!(!ask_webhook(&url, Some("denied")).await.unwrap());
+        assert!(!ask_webhook(&url, None).await.unwrap());
+        assert!(
+            !ask_webhook(&format!("http://{addr}/error"), Some("allowed"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_webhooks_time_out() {
+        let addr = spawn_webhook();
+        let error = ask_webhook(&format!("http://{addr}/slow"), Some("allowed"))
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[test]
+    fn decisions_are_cached_until_they_expire() {
+        let mut authorizations = Authorizations::default();
+        let allowed = (CHANNEL_ID, Some("allowed".to_owned()));
+        let denied = (CHANNEL_ID, Some("denied".to_owned()));
+        assert_eq!(authorizations.cached(&allowed), None);
+        authorizations.remember(allowed.clone(), true);
+        authorizations.remember(denied.clone(), false);
+        assert_eq!(authorizations.cached(&allowed), Some(true));
+        assert_eq!(authorizations.cached(&denied), Some(false));
+        assert_eq!(authorizations.cached(&(CHANNEL_ID, None)), None);
+
+        authorizations
+            .decisions
+            .insert(denied.clone(), (false, Instant::now()));
+        assert_eq!(authorizations.cached(&denied), None);
+        authorizations.prune();
+        assert_eq!(authorizations.decisions.len(), 1);
+        authorizations.forget_channel(CHANNEL_ID);
+        assert_eq!(authorizations.cached(&allowed), None);
+    }
+}
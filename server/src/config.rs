@@ -17,18 +17,90 @@ pub struct Config {
     pub log: String,
     pub log_format: LogFormat,
     pub database_url: String,
+    pub token_secret: Option<String>,
+    /// Name of the root user created on the first start.
+    pub admin_name: String,
+    /// Password of the root user created on the first start, generated if not set.
+    pub admin_password: Option<String>,
+    /// Number of hours after which login sessions expire.
+    pub session_lifetime_hours: i32,
+    /// Number of hours after which invitations expire, unless set when creating them.
+    pub invitation_lifetime_hours: i64,
+    /// Maximum number of hours after which invitations can be set to expire.
+    pub invitation_max_lifetime_hours: i64,
+    /// Number of failed logins with a user name after which it is locked out.
+    pub login_max_failures: u32,
+    /// Number of failed logins from a client IP after which it is locked out.
+    pub login_max_failures_per_ip: u32,
+    /// Number of seconds for which failed logins are remembered and lockouts last.
+    pub login_lockout_seconds: u64,
+    /// Number of days after which expired keys are deleted.
+    pub expired_keys_retention_days: i64,
+    /// Use the X-Forwarded-For header to get the IP address of clients.
+    pub trust_proxy: bool,
+    /// Number of trusted proxies in front of the server, each adding an X-Forwarded-For entry.
+    pub trusted_proxy_hops: usize,
+    /// Rate of anonymous subscriptions to public channels allowed per client IP.
+    pub anonymous_subscriptions_per_second: f64,
+    /// Number of anonymous subscriptions a client IP can make in a burst.
+    pub anonymous_subscriptions_burst: f64,
+    /// Maximum number of concurrent subscriptions with each key.
+    pub max_connections_per_key: usize,
+    /// Maximum number of concurrent subscriptions to each channel.
+    pub max_connections_per_channel: usize,
+    /// Maximum number of concurrent subscriptions from each client IP.
+    pub max_connections_per_ip: usize,
+    /// Maximum number of anonymous connections to each public channel.
+    pub anonymous_connections_per_channel: usize,
+    /// Number of seconds for which the decisions of authorization webhooks are cached.
+    pub authorization_cache_ttl: u64,
+    /// Number of seconds to wait for authorization webhooks to respond.
+    pub authorization_timeout: u64,
 }
 
 pub static CONFIG: Lazy<Config> = Lazy::new(|| {
     dotenv().ok();
-    Figment::new()
+    let config: Config = Figment::new()
         // default values
         .join(Serialized::default("port", 8080))
         .join(Serialized::default("log", "error"))
         .join(Serialized::default("log_format", LogFormat::Json))
+        .join(Serialized::default("admin_name", "admin"))
+        .join(Serialized::default("session_lifetime_hours", 24))
+        .join(Serialized::default("invitation_lifetime_hours", 7 * 24))
+        .join(Serialized::default(
+            "invitation_max_lifetime_hours",
+            30 * 24,
+        ))
+        .join(Serialized::default("login_max_failures", 5))
+        .join(Serialized::default("login_max_failures_per_ip", 50))
+        .join(Serialized::default("login_lockout_seconds", 15 * 60))
+        .join(Serialized::default("expired_keys_retention_days", 30))
+        .join(Serialized::default("trust_proxy", false))
+        .join(Serialized::default("trusted_proxy_hops", 1))
+        .join(Serialized::default(
+            "anonymous_subscriptions_per_second",
+            1.0,
+        ))
+        .join(Serialized::default("anonymous_subscriptions_burst", 10.0))
+        .join(Serialized::default("max_connections_per_key", 1000))
+        .join(Serialized::default("max_connections_per_channel", 10000))
+        .join(Serialized::default("max_connections_per_ip", 100))
+        .join(Serialized::default(
+            "anonymous_connections_per_channel",
+            1000,
+        ))
+        .join(Serialized::default("authorization_cache_ttl", 60))
+        .join(Serialized::default("authorization_timeout", 5))
         // get the database_url and port config values with or without the MERCURY_ prefix
         .merge(Env::raw().only(&["port", "database_url"]))
         .merge(Env::prefixed("MERCURY_"))
         .extract()
-        .expect("config")
+        .expect("config");
+    // a rate of 0 would make clients wait forever
+    assert!(
+        config.anonymous_subscriptions_per_second > 0.0,
+        "MERCURY_ANONYMOUS_SUBSCRIPTIONS_PER_SECOND must be positive",
+    );
+    config
 });
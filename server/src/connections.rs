@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use uuid::Uuid;
+
+/// What open connections are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ConnectionScope {
+    Key(Uuid),
+    Channel(Uuid),
+    Ip(IpAddr),
+    /// The anonymous connections to a public channel.
+    AnonymousChannel(Uuid),
+}
+
+impl ConnectionScope {
+    /// What the scope is, in error messages.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Key(_) => "key",
+            Self::Channel(_) => "channel",
+            Self::Ip(_) => "client IP",
+            Self::AnonymousChannel(_) => "channel without a key",
+        }
+    }
+}
+
+/// Counts of open connections, one for each key.
+#[derive(Debug)]
+pub(crate) struct Connections<K>(Arc<Mutex<HashMap<K, usize>>>);
+
+impl<K> Default for Connections<K> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(HashMap::new())))
+    }
+}
+
+impl<K> Clone for Connections<K> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<K: Eq + Hash + Copy> Connections<K> {
+    /// Count a new connection for each key, unless one of them already has its maximum number of
+    /// connections, in which case that key is returned and nothing is counted.
+    ///
+    /// The connection is counted until the returned guard is dropped.
+    pub(crate) fn acquire(&self, limits: &[(K, usize)]) -> Result<ConnectionGuard<K>, K> {
+        let mut counts = self.0.lock().expect("poisoned connections lock");
+        if let Some(&(key, _)) = limits
+            .iter()
+            .find(|(key, max)| counts.get(key).copied().unwrap_or(0) >= *max)
+        {
+            return Err(key);
+        }
+        for (key, _) in limits {
+            *counts.entry(*key).or_insert(0) += 1;
+        }
+        Ok(ConnectionGuard {
+            connections: self.clone(),
+            keys: limits.iter().map(|&(key, _)| key).collect(),
+        })
+    }
+
+    /// Get the number of open connections of every key that has some.
+    pub(crate) fn counts(&self) -> HashMap<K, usize> {
+        self.0.lock().expect("poisoned connections lock").clone()
+    }
+}
+
+/// An open connection, counted until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard<K: Eq + Hash> {
+    connections: Connections<K>,
+    keys: Vec<K>,
+}
+
+impl<K: Eq + Hash> Drop for ConnectionGuard<K> {
+    fn drop(&mut self) {
+        let mut counts = self
+            .connections
+            .0
+            .lock()
+            .expect("poisoned connections lock");
+        for key in &self.keys {
+            if let Some(count) = counts.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(key);
+                }
+            }
+        }
+    }
+}
@@ -12,7 +12,7 @@ pub(crate) async fn health(State(state): State<SharedState>) -> Result<()> {
             .fetch_one(&state.read().await.pool)
             .await?
             .expect("NULL from SELECT scalar"),
-        4
+        22
     );
     assert_eq!(
         sqlx::query_scalar!(
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tracing::{error, info};
+
+use crate::config::CONFIG;
+use crate::models::invitation::Invitation;
+use crate::models::key::Key;
+use crate::models::session::Session;
+use crate::state::SharedState;
+
+/// Start the background jobs.
+pub(crate) fn spawn(state: SharedState) {
+    tokio::spawn(purge_expired_keys(Arc::clone(&state)));
+    tokio::spawn(purge_expired_sessions(Arc::clone(&state)));
+    tokio::spawn(purge_expired_invitations(Arc::clone(&state)));
+    tokio::spawn(record_key_usage(Arc::clone(&state)));
+    tokio::spawn(reset_quotas(Arc::clone(&state)));
+    tokio::spawn(prune_caches(state));
+}
+
+/// Every hour, delete the keys that expired more than `expired_keys_retention_days` days ago.
+async fn purge_expired_keys(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let expired_before =
+            Utc::now() - chrono::Duration::days(CONFIG.expired_keys_retention_days);
+        match Key::delete_expired(&state.read().await.pool, expired_before).await {
+            Ok(count) => info!(count, "purged expired keys"),
+            Err(error) => error!(?error),
+        }
+    }
+}
+
+/// Every hour, delete the expired login sessions.
+async fn purge_expired_sessions(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match Session::delete_expired(&state.read().await.pool).await {
+            Ok(count) => info!(count, "purged expired sessions"),
+            Err(error) => error!(?error),
+        }
+    }
+}
+
+/// Every hour, delete the expired invitations.
+async fn purge_expired_invitations(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match Invitation::delete_expired(&state.read().await.pool).await {
+            Ok(count) => info!(count, "purged expired invitations"),
+            Err(error) => error!(?error),
+        }
+    }
+}
+
+/// Every minute, write the key usage collected in memory to the database.
+async fn record_key_usage(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let usage = state.write().await.key_usage.take();
+        if !usage.is_empty() {
+            if let Err(error) = Key::record_usage(&state.read().await.pool, &usage).await {
+                error!(?error);
+                // recorded on the next run instead
+                state.write().await.key_usage.merge(usage);
+            }
+        }
+    }
+}
+
+/// Every minute, reset the quota counters of the previous day or month, so that key listings are
+/// up to date.
+async fn reset_quotas(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        if let Err(error) = Key::reset_quotas(&state.read().await.pool).await {
+            error!(?error);
+        }
+    }
+}
+
+/// Every minute, forget the rate limiter buckets that are full, the expired authorization
+/// decisions and the old failed login attempts.
+async fn prune_caches(state: SharedState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let mut state = state.write().await;
+        state.anonymous_rate_limiter.prune();
+        state.publish_rate_limiter.prune();
+        state.authorizations.prune();
+        state.login_attempts.prune();
+    }
+}
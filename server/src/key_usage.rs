@@ -0,0 +1,80 @@
+use std::collections::hash_map::{Entry, IntoIter, Iter};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::models::key::Key;
+
+/// The usage of a key since it was last recorded in the database.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Usage {
+    pub(crate) last_used_at: DateTime<Utc>,
+    pub(crate) publish_count: i64,
+    pub(crate) subscribe_count: i64,
+}
+
+/// The usage of all keys, kept in memory so that the database is not updated on every request.
+#[derive(Debug, Default)]
+pub(crate) struct KeyUsage(HashMap<Uuid, Usage>);
+
+impl KeyUsage {
+    fn get(&mut self, key: &Key) -> &mut Usage {
+        let usage = self.0.entry(key.id).or_insert(Usage {
+            last_used_at: Utc::now(),
+            publish_count: 0,
+            subscribe_count: 0,
+        });
+        usage.last_used_at = Utc::now();
+        usage
+    }
+
+    /// Record that the key published a message.
+    pub(crate) fn record_publish(&mut self, key: &Key) {
+        self.get(key).publish_count += 1;
+    }
+
+    /// Record that the key opened a subscription.
+    pub(crate) fn record_subscribe(&mut self, key: &Key) {
+        self.get(key).subscribe_count += 1;
+    }
+
+    /// Take the usage recorded so far, leaving it empty.
+    pub(crate) fn take(&mut self) -> Self {
+        std::mem::take(self)
+    }
+
+    /// Add back usage that could not be recorded.
+    pub(crate) fn merge(&mut self, other: Self) {
+        for (id, other_usage) in other {
+            match self.0.entry(id) {
+                Entry::Occupied(mut entry) => {
+                    let usage = entry.get_mut();
+                    usage.last_used_at = usage.last_used_at.max(other_usage.last_used_at);
+                    usage.publish_count += other_usage.publish_count;
+                    usage.subscribe_count += other_usage.subscribe_count;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(other_usage);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Iter<'_, Uuid, Usage> {
+        self.0.iter()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for KeyUsage {
+    type Item = (Uuid, Usage);
+    type IntoIter = IntoIter<Uuid, Usage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
@@ -1,8 +1,14 @@
 pub(crate) mod api;
+pub(crate) mod authorizations;
 pub mod config;
+pub(crate) mod connections;
 pub mod database;
 mod health;
+mod jobs;
+pub(crate) mod key_usage;
+pub(crate) mod login_attempts;
 pub(crate) mod models;
+pub(crate) mod rate_limiter;
 pub(crate) mod senders;
 pub(crate) mod sse;
 mod state;
@@ -10,26 +16,60 @@ mod state;
 use std::sync::Arc;
 
 use anyhow::Result;
+use axum::body::Body;
+use axum::http::{Request, Uri};
 use axum::routing::get;
 use axum::Router;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
+use tracing::Span;
 
+use crate::api::extract::key::TOKEN_QUERY_PARAMETER;
 use crate::state::{AppState, SharedState};
 
+/// Format the URI, hiding the value of the key token query parameter.
+fn redact_uri(uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => {
+            let query = query
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, _)) if name == TOKEN_QUERY_PARAMETER => {
+                        format!("{name}=[REDACTED]")
+                    }
+                    _ => pair.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+            format!("{}?{}", uri.path(), query)
+        }
+        None => uri.to_string(),
+    }
+}
+
+/// Create the request span, like `DefaultMakeSpan` but without leaking key tokens.
+fn make_span(request: &Request<Body>) -> Span {
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %redact_uri(request.uri()),
+        version = ?request.version(),
+    )
+}
+
 pub async fn app() -> Result<Router<SharedState>> {
     let state = AppState::new().await?;
+    jobs::spawn(Arc::clone(&state));
     let cors_layer = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
-    let trace_layer = TraceLayer::new_for_http();
+    let trace_layer = TraceLayer::new_for_http().make_span_with(make_span);
 
     let router = Router::with_state(Arc::clone(&state))
         .route("/health", get(health::health))
-        .nest("/api", api::app(Arc::clone(&state)))
+        .nest("/api", api::app(Arc::clone(&state)).layer(cors_layer))
         .nest("/sse", sse::app(Arc::clone(&state)))
-        .layer(cors_layer)
         .layer(trace_layer);
 
     Ok(router)
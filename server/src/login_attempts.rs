@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::CONFIG;
+
+/// Delay before answering the first failed attempt, doubled for each following one.
+const BASE_DELAY: Duration = Duration::from_millis(250);
+/// Maximum delay before answering a failed attempt.
+const MAX_DELAY: Duration = Duration::from_secs(8);
+/// Maximum number of names and IPs whose failures are remembered, so that attempts with many
+/// unknown names cannot use up the memory.
+const MAX_SCOPES: usize = 100_000;
+/// Number of names and IPs forgotten at once when there are too many, so that room is not made on
+/// every attempt.
+const EVICTED_SCOPES: usize = MAX_SCOPES / 10;
+
+/// What failed login attempts are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    /// The user name, whether or not a user has it.
+    Name(String),
+    Ip(IpAddr),
+}
+
+impl Scope {
+    /// Number of failures after which the scope is locked out.
+    fn max_failures(&self) -> u32 {
+        match self {
+            Self::Name(_) => CONFIG.login_max_failures,
+            Self::Ip(_) => CONFIG.login_max_failures_per_ip,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last_at: Instant,
+}
+
+/// Recent failed login attempts, by user name and by client IP.
+#[derive(Debug, Default)]
+pub(crate) struct LoginAttempts(HashMap<Scope, Failures>);
+
+fn lockout() -> Duration {
+    Duration::from_secs(CONFIG.login_lockout_seconds)
+}
+
+impl LoginAttempts {
+    /// Start a login attempt, counting it as failed until it is known not to be, so that
+    /// concurrent attempts cannot get past the lockout.
+    ///
+    /// Returns how long to wait before answering the attempt if it fails, or, if the name or the IP
+    /// is locked out, how long to wait before retrying. The delay grows with the failures with the
+    /// name only, so that users behind a shared IP do not slow each other down.
+    pub(crate) fn begin(&mut self, name: &str, ip: IpAddr) -> Result<Duration, Duration> {
+        let now = Instant::now();
+        let name = Scope::Name(name.to_owned());
+        let ip = Scope::Ip(ip);
+        let retry_after = [&name, &ip]
+            .into_iter()
+            .filter_map(|scope| {
+                self.0
+                    .get(scope)
+                    .filter(|f| f.count >= scope.max_failures())
+            })
+            .map(|failures| (failures.last_at + lockout()).saturating_duration_since(now))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if !retry_after.is_zero() {
+            return Err(retry_after);
+        }
+        self.count_failure(ip, now);
+        let count = self.count_failure(name, now);
+        Ok(BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(count - 1))
+            .min(MAX_DELAY))
+    }
+
+    /// End a successful login attempt, forgetting the failures with the name.
+    pub(crate) fn succeed(&mut self, name: &str, ip: IpAddr) {
+        self.0.remove(&Scope::Name(name.to_owned()));
+        self.uncount_failure(&Scope::Ip(ip));
+    }
+
+    /// End a login attempt that neither succeeded nor failed, such as one missing a TOTP code.
+    pub(crate) fn cancel(&mut self, name: &str, ip: IpAddr) {
+        self.uncount_failure(&Scope::Name(name.to_owned()));
+        self.uncount_failure(&Scope::Ip(ip));
+    }
+
+    /// Count a failed attempt in a scope, returning the number of recent failures in it.
+    fn count_failure(&mut self, scope: Scope, now: Instant) -> u32 {
+        if self.0.len() >= MAX_SCOPES && !self.0.contains_key(&scope) {
+            self.make_room();
+        }
+        let failures = self.0.entry(scope).or_insert(Failures {
+            count: 0,
+            last_at: now,
+        });
+        // failures older than the lockout duration are forgotten
+        if now.duration_since(failures.last_at) >= lockout() {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last_at = now;
+        failures.count
+    }
+
+    fn uncount_failure(&mut self, scope: &Scope) {
+        if let Some(failures) = self.0.get_mut(scope) {
+            failures.count = failures.count.saturating_sub(1);
+        }
+    }
+
+    /// Forget the old failures, or else the least recent ones of the names and IPs that are not
+    /// locked out.
+    fn make_room(&mut self) {
+        self.prune();
+        if self.0.len() < MAX_SCOPES {
+            return;
+        }
+        let mut evictable: Vec<(Instant, Scope)> = self
+            .0
+            .iter()
+            .filter(|(scope, failures)| failures.count < scope.max_failures())
+            .map(|(scope, failures)| (failures.last_at, scope.clone()))
+            .collect();
+        let count = EVICTED_SCOPES.min(evictable.len());
+        if count == 0 {
+            return;
+        }
+        evictable.select_nth_unstable_by_key(count - 1, |&(last_at, _)| last_at);
+        for (_, scope) in evictable.into_iter().take(count) {
+            self.0.remove(&scope);
+        }
+    }
+
+    /// Forget the failed attempts older than the lockout duration.
+    pub(crate) fn prune(&mut self) {
+        let now = Instant::now();
+        self.0
+            .retain(|_, failures| now.duration_since(failures.last_at) < lockout());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn concurrent_attempts_are_locked_out() {
+        let mut login_attempts = LoginAttempts::default();
+        // none of the attempts has ended yet
+        for _ in 0..CONFIG.login_max_failures {
+            assert!(login_attempts.begin("name", IP).is_ok());
+        }
+        assert!(login_attempts.begin("name", IP).is_err());
+        assert!(login_attempts.begin("other", IP).is_ok());
+    }
+
+    #[test]
+    fn delays_grow_with_failures() {
+        let mut login_attempts = LoginAttempts::default();
+        assert_eq!(login_attempts.begin("name", IP), Ok(BASE_DELAY));
+        assert_eq!(login_attempts.begin("name", IP), Ok(BASE_DELAY * 2));
+        login_attempts.cancel("name", IP);
+        assert_eq!(login_attempts.begin("name", IP), Ok(BASE_DELAY * 2));
+        login_attempts.succeed("name", IP);
+        assert_eq!(login_attempts.begin("name", IP), Ok(BASE_DELAY));
+    }
+
+    #[test]
+    fn locked_out_names_are_not_forgotten_to_make_room() {
+        let mut login_attempts = LoginAttempts::default();
+        for _ in 0..CONFIG.login_max_failures {
+            assert!(login_attempts.begin("name", IP).is_ok());
+        }
+        let now = Instant::now();
+        for i in 0..2 * MAX_SCOPES {
+            login_attempts.count_failure(Scope::Name(i.to_string()), now);
+        }
+        assert!(login_attempts.0.len() <= MAX_SCOPES);
+        assert!(login_attempts.begin("name", IP).is_err());
+    }
+}
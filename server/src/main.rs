@@ -23,7 +23,7 @@ async fn main() -> Result<()> {
         IpAddr::V4(Ipv4Addr::UNSPECIFIED),
         CONFIG.port,
     ))
-    .serve(app.into_make_service())
+    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
     .await?;
 
     Ok(())
@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use self::error::{Error, Result};
+use crate::models::user::{Permission, User};
+
+/// Prefix of access tokens, telling them apart from session tokens.
+pub(crate) const ACCESS_TOKEN_PREFIX: &str = "mercury_pat_";
+
+/// What an access token allows, on top of its user's role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "access_token_scope")]
+#[sqlx(rename_all = "kebab-case")]
+pub(crate) enum Scope {
+    /// Everything the user can do, except managing their own account.
+    Full,
+    /// Viewing channels and keys.
+    ReadOnly,
+    /// Viewing and managing channels.
+    Channels,
+}
+
+impl Scope {
+    /// Returns whether the scope allows an action that requires the permission.
+    pub(crate) fn allows(self, permission: Permission) -> bool {
+        match self {
+            Self::Full => permission != Permission::ManageAccount,
+            Self::ReadOnly => {
+                permission == Permission::ViewChannels || permission == Permission::ViewKeys
+            }
+            Self::Channels => {
+                permission == Permission::ViewChannels || permission == Permission::ManageChannels
+            }
+        }
+    }
+}
+
+/// A named token a user can authenticate with instead of their password, for automation.
+///
+/// Only a hash of the token is stored.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AccessToken {
+    pub(crate) id: Uuid,
+    #[serde(skip_serializing)]
+    pub(crate) user_id: Uuid,
+    name: String,
+    pub(crate) scope: Scope,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+/// CRUD
+impl AccessToken {
+    /// Create a new access token for the user.
+    ///
+    /// Returns the access token and its secret value. The value will only be returned once, when
+    /// the access token is created.
+    pub(crate) async fn new(
+        pool: &PgPool,
+        user: &User,
+        name: &str,
+        scope: Scope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(Self, String)> {
+        let token = sqlx::query_scalar!(
+            r#"SELECT $1 || encode(gen_random_bytes(32), 'hex') AS "token!""#,
+            ACCESS_TOKEN_PREFIX,
+        )
+        .fetch_one(pool)
+        .await?;
+        let access_token = sqlx::query_as!(
+            Self,
+            r#"
+            INSERT INTO "AccessToken" (user_id, name, token_hash, scope, expires_at)
+                VALUES ($1, $2, digest($3, 'sha256'), $4, $5)
+            RETURNING id, user_id, name, scope as "scope: _", created_at, expires_at, last_used_at
+            "#,
+            user.id,
+            name,
+            token,
+            scope as Scope,
+            expires_at,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok((access_token, token))
+    }
+
+    /// Get an unexpired access token by its value, recording that it was used.
+    pub(crate) async fn use_token(pool: &PgPool, token: &str) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            UPDATE "AccessToken"
+                SET last_used_at = now()
+                WHERE token_hash = digest($1, 'sha256')
+                    AND (expires_at IS NULL OR expires_at > now())
+            RETURNING id, user_id, name, scope as "scope: _", created_at, expires_at, last_used_at
+            "#,
+            token,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::InvalidAccessToken)
+    }
+
+    /// Get an access token of the user by its id.
+    pub(crate) async fn get(pool: &PgPool, user: &User, id: Uuid) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, user_id, name, scope as "scope: _", created_at, expires_at, last_used_at
+                FROM "AccessToken"
+                WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user.id,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Get all the access tokens of the user, including the expired ones.
+    pub(crate) async fn get_all(pool: &PgPool, user: &User) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, user_id, name, scope as "scope: _", created_at, expires_at, last_used_at
+                FROM "AccessToken"
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+            user.id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Revoke the access token.
+    pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "AccessToken"
+                WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::{debug, error};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error("Access token not found")]
+        NotFound,
+        #[error("Invalid or expired access token")]
+        InvalidAccessToken,
+        #[error("Duplicate access token name")]
+        DuplicateName,
+    }
+
+    impl From<sqlx::Error> for Error {
+        fn from(error: sqlx::Error) -> Self {
+            if let Some(database_error) = error.as_database_error() {
+                if database_error.constraint() == Some("AccessToken_user_id_name_key") {
+                    return Self::DuplicateName;
+                }
+            }
+            error!(?error);
+            panic!("unknown database error");
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+                Error::InvalidAccessToken => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+                Error::DuplicateName => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            }
+        }
+    }
+}
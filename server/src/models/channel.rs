@@ -6,22 +6,48 @@ use uuid::Uuid;
 
 use self::error::{Error, Result};
 use crate::models::key::Key;
+use crate::models::user::User;
 
 pub(crate) struct RawChannel {
     id: Uuid,
     name: String,
     schema: Value,
+    public: bool,
+    authorization_url: Option<String>,
+    publish_rate_limit: Option<f64>,
+    publish_byte_rate_limit: Option<f64>,
+    created_by: Option<Uuid>,
+    project_id: Uuid,
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct Channel {
     pub(crate) id: Uuid,
-    name: String,
+    pub(crate) name: String,
     schema: Value,
+    /// Whether the channel can be subscribed to without a key.
+    pub(crate) public: bool,
+    /// The URL that decides whether subscribers can subscribe to the channel.
+    pub(crate) authorization_url: Option<String>,
+    /// Maximum number of messages that can be published on the channel per second.
+    pub(crate) publish_rate_limit: Option<f64>,
+    /// Maximum number of bytes that can be published on the channel per second.
+    pub(crate) publish_byte_rate_limit: Option<f64>,
+    /// The user who created the channel.
+    created_by: Option<Uuid>,
+    pub(crate) project_id: Uuid,
     #[serde(skip_serializing)]
     compiled_schema: JSONSchema,
 }
 
+/// The publish rate limits of a channel, all optional.
+#[derive(Debug)]
+pub(crate) struct ChannelLimits {
+    pub(crate) publish_rate_limit: Option<f64>,
+    pub(crate) publish_byte_rate_limit: Option<f64>,
+}
+
 /// CRUD
 impl Channel {
     /// Create a `Channel` from a `RawChannel`.
@@ -32,22 +58,47 @@ impl Channel {
             compiled_schema: JSONSchema::compile(&raw_channel.schema)
                 .expect("invalid schema in database"),
             schema: raw_channel.schema,
+            public: raw_channel.public,
+            authorization_url: raw_channel.authorization_url,
+            publish_rate_limit: raw_channel.publish_rate_limit,
+            publish_byte_rate_limit: raw_channel.publish_byte_rate_limit,
+            created_by: raw_channel.created_by,
+            project_id: raw_channel.project_id,
         }
     }
 
-    /// Create a new channel.
-    pub(crate) async fn new(pool: &PgPool, name: &str, schema: &Value) -> Result<Self> {
+    /// Create a new channel in a project.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn new(
+        pool: &PgPool,
+        created_by: &User,
+        project_id: Uuid,
+        name: &str,
+        schema: &Value,
+        public: bool,
+        authorization_url: Option<&str>,
+        limits: ChannelLimits,
+    ) -> Result<Self> {
         JSONSchema::compile(schema)?;
         Ok(Self::from_raw_channel(
             sqlx::query_as!(
                 RawChannel,
                 r#"
-                INSERT INTO "Channel" (name, schema)
-                    VALUES ($1, $2)
+                INSERT INTO "Channel" (
+                    name, schema, public, authorization_url, publish_rate_limit,
+                    publish_byte_rate_limit, created_by, project_id
+                )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                 RETURNING *
                 "#,
                 name,
                 schema,
+                public,
+                authorization_url,
+                limits.publish_rate_limit,
+                limits.publish_byte_rate_limit,
+                created_by.id,
+                project_id,
             )
             .fetch_one(pool)
             .await?,
@@ -71,15 +122,16 @@ impl Channel {
         ))
     }
 
-    /// Get a channel by its name.
-    pub(crate) async fn get_by_name(pool: &PgPool, name: &str) -> Result<Self> {
+    /// Get a channel by its project and its name.
+    pub(crate) async fn get_by_name(pool: &PgPool, project_id: Uuid, name: &str) -> Result<Self> {
         Ok(Self::from_raw_channel(
             sqlx::query_as!(
                 RawChannel,
                 r#"
                 SELECT * FROM "Channel"
-                    WHERE name = $1
+                    WHERE project_id = $1 AND name = $2
                 "#,
+                project_id,
                 name,
             )
             .fetch_optional(pool)
@@ -88,27 +140,64 @@ impl Channel {
         ))
     }
 
-    /// Get all channels.
-    pub(crate) async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
-        Ok(sqlx::query_as!(RawChannel, r#"SELECT * FROM "Channel""#)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(Self::from_raw_channel)
-            .collect())
+    /// Get a channel, if the user can see it.
+    pub(crate) async fn get_visible(pool: &PgPool, id: Uuid, user: &User) -> Result<Self> {
+        let channel = Self::get(pool, id).await?;
+        if channel.is_visible_to(pool, user).await? {
+            Ok(channel)
+        } else {
+            Err(Error::NotFound)
+        }
     }
 
-    /// Get channels by their key.
-    pub(crate) async fn get_from_key(pool: &PgPool, key: &Key) -> Result<Vec<Self>> {
+    /// Get all channels that the user can see, only those of a project if one is given.
+    pub(crate) async fn get_all(
+        pool: &PgPool,
+        user: &User,
+        project_id: Option<Uuid>,
+    ) -> Result<Vec<Self>> {
         Ok(sqlx::query_as!(
             RawChannel,
             r#"
-            SELECT id, name, schema FROM "Channel"
-                JOIN "Access"
-                    ON "Channel".id = "Access".channel_id
-                WHERE key_id = $1
+            SELECT * FROM "Channel"
+                WHERE ($2::uuid IS NULL OR project_id = $2) AND (
+                    user_manages($1, created_by, project_id)
+                        OR id IN (SELECT channel_id FROM "ChannelCollaborator" WHERE user_id = $1)
+                )
+            "#,
+            user.id,
+            project_id,
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(Self::from_raw_channel)
+        .collect())
+    }
+
+    /// Get the channels that the user can see by their key, including the channels matching the
+    /// key's patterns.
+    pub(crate) async fn get_from_key(pool: &PgPool, key: &Key, user: &User) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            RawChannel,
+            r#"
+            SELECT id, name, schema, public, authorization_url, publish_rate_limit,
+                publish_byte_rate_limit, created_by, project_id
+            FROM "Channel"
+                WHERE project_id = $3 AND (
+                    id IN (SELECT channel_id FROM "Access" WHERE key_id = $1)
+                        OR EXISTS (
+                            SELECT FROM "PatternAccess"
+                                WHERE key_id = $1 AND channel_name_matches(name, pattern)
+                        )
+                ) AND (
+                    user_manages($2, created_by, project_id)
+                        OR id IN (SELECT channel_id FROM "ChannelCollaborator" WHERE user_id = $2)
+                )
             "#,
             key.id,
+            user.id,
+            key.project_id,
         )
         .fetch_all(pool)
         .await?
@@ -117,6 +206,64 @@ impl Channel {
         .collect())
     }
 
+    /// Set whether the channel can be subscribed to without a key.
+    pub(crate) async fn set_public(&mut self, pool: &PgPool, public: bool) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Channel"
+                SET public = $2
+                WHERE id = $1
+            "#,
+            self.id,
+            public,
+        )
+        .execute(pool)
+        .await?;
+        self.public = public;
+        Ok(())
+    }
+
+    /// Set the URL that decides whether subscribers can subscribe to the channel.
+    pub(crate) async fn set_authorization_url(
+        &mut self,
+        pool: &PgPool,
+        authorization_url: Option<String>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Channel"
+                SET authorization_url = $2
+                WHERE id = $1
+            "#,
+            self.id,
+            authorization_url,
+        )
+        .execute(pool)
+        .await?;
+        self.authorization_url = authorization_url;
+        Ok(())
+    }
+
+    /// Set the channel's publish rate limits.
+    pub(crate) async fn set_limits(&mut self, pool: &PgPool, limits: ChannelLimits) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Channel"
+                SET publish_rate_limit = $2,
+                    publish_byte_rate_limit = $3
+                WHERE id = $1
+            "#,
+            self.id,
+            limits.publish_rate_limit,
+            limits.publish_byte_rate_limit,
+        )
+        .execute(pool)
+        .await?;
+        self.publish_rate_limit = limits.publish_rate_limit;
+        self.publish_byte_rate_limit = limits.publish_byte_rate_limit;
+        Ok(())
+    }
+
     /// Delete the channel.
     pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
         sqlx::query!(
@@ -132,6 +279,87 @@ impl Channel {
     }
 }
 
+/// Ownership
+impl Channel {
+    /// Returns whether the user created the channel or is an ancestor of its creator.
+    ///
+    /// Only those users can share the channel with others.
+    pub(crate) async fn is_managed_by(&self, pool: &PgPool, user: &User) -> Result<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT user_manages($1, $2, $3)"#,
+            user.id,
+            self.created_by,
+            self.project_id,
+        )
+        .fetch_one(pool)
+        .await
+        .map(|option| option.expect("NULL from SELECT scalar"))?)
+    }
+
+    /// Returns whether the user manages the channel or it was shared with them.
+    pub(crate) async fn is_visible_to(&self, pool: &PgPool, user: &User) -> Result<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT user_manages($1, $2, $3) OR EXISTS (
+                SELECT FROM "ChannelCollaborator"
+                    WHERE channel_id = $4 AND user_id = $1
+            )
+            "#,
+            user.id,
+            self.created_by,
+            self.project_id,
+            self.id,
+        )
+        .fetch_one(pool)
+        .await
+        .map(|option| option.expect("NULL from SELECT scalar"))?)
+    }
+
+    /// Get the ids of the users the channel is shared with.
+    pub(crate) async fn collaborators(&self, pool: &PgPool) -> Result<Vec<Uuid>> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT user_id FROM "ChannelCollaborator"
+                WHERE channel_id = $1
+            "#,
+            self.id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Share the channel with a user.
+    pub(crate) async fn share(&self, pool: &PgPool, user: &User) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO "ChannelCollaborator" (channel_id, user_id)
+                VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            self.id,
+            user.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Stop sharing the channel with a user.
+    pub(crate) async fn unshare(&self, pool: &PgPool, user_id: Uuid) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "ChannelCollaborator"
+                WHERE channel_id = $1 AND user_id = $2
+            "#,
+            self.id,
+            user_id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
 impl Channel {
     /// Run validation on the instance, only returning a boolean indicating success or failure.
     pub(crate) fn is_valid(&self, instance: &Value) -> bool {
@@ -164,13 +392,20 @@ pub(crate) mod error {
         NotFound,
         #[error("Duplicate channel name")]
         DuplicateName,
+        #[error("Invalid limits, rate limits must be positive")]
+        InvalidLimits,
     }
 
     impl From<sqlx::Error> for Error {
         fn from(error: sqlx::Error) -> Self {
             if let Some(database_error) = error.as_database_error() {
-                if database_error.constraint() == Some("Channel_name_key") {
-                    return Self::DuplicateName;
+                match database_error.constraint() {
+                    Some("Channel_project_id_name_key") => return Self::DuplicateName,
+                    Some(
+                        "Channel_publish_rate_limit_check"
+                        | "Channel_publish_byte_rate_limit_check",
+                    ) => return Self::InvalidLimits,
+                    _ => {}
                 }
             }
             error!(?error);
@@ -193,6 +428,7 @@ pub(crate) mod error {
                 }
                 Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
                 Error::DuplicateName => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+                Error::InvalidLimits => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
             }
         }
     }
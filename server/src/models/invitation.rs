@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use self::error::{Error, Result};
+use crate::models::user::{Role, User};
+
+/// An invitation to create a user, with a name and password of their choice.
+///
+/// Only a hash of the invitation's token is stored.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Invitation {
+    pub(crate) id: Uuid,
+    /// The user the invitee will be a child of.
+    parent_id: Uuid,
+    role: Role,
+    project_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+/// CRUD
+impl Invitation {
+    /// Create a new invitation.
+    ///
+    /// Returns the invitation and its token. The token will only be returned once, when the
+    /// invitation is created.
+    pub(crate) async fn new(
+        pool: &PgPool,
+        parent: &User,
+        role: Role,
+        project_id: Option<Uuid>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(Self, String)> {
+        let token = sqlx::query_scalar!(r#"SELECT encode(gen_random_bytes(32), 'hex')"#)
+            .fetch_one(pool)
+            .await?
+            .expect("NULL from SELECT scalar");
+        let invitation = sqlx::query_as!(
+            Self,
+            r#"
+            INSERT INTO "Invitation" (token_hash, parent_id, role, project_id, expires_at)
+                VALUES (digest($1, 'sha256'), $2, $3, $4, $5)
+            RETURNING id, parent_id, role as "role: _", project_id, created_at, expires_at
+            "#,
+            token,
+            parent.id,
+            role as Role,
+            project_id,
+            expires_at,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok((invitation, token))
+    }
+
+    /// Get a pending invitation by its id, if it is to join the user's subtree.
+    pub(crate) async fn get(pool: &PgPool, user: &User, id: Uuid) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, parent_id, role as "role: _", project_id, created_at, expires_at
+                FROM "Invitation"
+                WHERE id = $1 AND expires_at > now() AND user_descends_from(parent_id, $2)
+            "#,
+            id,
+            user.id,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Get all pending invitations to join the user's subtree.
+    pub(crate) async fn get_all(pool: &PgPool, user: &User) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, parent_id, role as "role: _", project_id, created_at, expires_at
+                FROM "Invitation"
+                WHERE expires_at > now() AND user_descends_from(parent_id, $1)
+                ORDER BY created_at
+            "#,
+            user.id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Create the invited user, with their chosen name and password, and delete the invitation.
+    pub(crate) async fn redeem(
+        pool: &PgPool,
+        token: &str,
+        name: &str,
+        password: &str,
+    ) -> Result<User> {
+        let mut transaction = pool.begin().await?;
+        let invitation = sqlx::query_as!(
+            Self,
+            r#"
+            DELETE FROM "Invitation"
+                WHERE token_hash = digest($1, 'sha256') AND expires_at > now()
+            RETURNING id, parent_id, role as "role: _", project_id, created_at, expires_at
+            "#,
+            token,
+        )
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or(Error::InvalidInvitation)?;
+        let parent = User::get(pool, invitation.parent_id).await?;
+        let user = User::new(
+            &mut transaction,
+            name,
+            password,
+            &parent,
+            invitation.role,
+            invitation.project_id,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(user)
+    }
+
+    /// Delete all expired invitations.
+    ///
+    /// Returns the number of deleted invitations.
+    pub(crate) async fn delete_expired(pool: &PgPool) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM "Invitation"
+                WHERE expires_at <= now()
+            "#,
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+
+    /// Revoke the invitation.
+    pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "Invitation"
+                WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::{debug, error};
+
+    use crate::models::user;
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error(transparent)]
+        UserError(#[from] user::error::Error),
+        #[error("Invitation not found")]
+        NotFound,
+        #[error("Invalid or expired invitation")]
+        InvalidInvitation,
+    }
+
+    impl From<sqlx::Error> for Error {
+        fn from(error: sqlx::Error) -> Self {
+            error!(?error);
+            panic!("unknown database error");
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::UserError(error) => error.into_response(),
+                Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+                Error::InvalidInvitation => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+            }
+        }
+    }
+}
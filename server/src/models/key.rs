@@ -1,23 +1,62 @@
-use axum::extract::{FromRef, FromRequestParts};
-use axum::headers::authorization::Basic;
-use axum::headers::Authorization;
-use axum::http::request::Parts;
-use axum::{async_trait, TypedHeader};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Postgres, QueryBuilder};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
 use self::error::{Error, Result};
+use crate::key_usage::KeyUsage;
 use crate::models::channel::Channel;
-use crate::state::SharedState;
+use crate::models::user::User;
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, sqlx::Type)]
+/// Something a key can be allowed to do on a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
 #[serde(rename_all = "camelCase")]
-#[sqlx(type_name = "keytype")]
+#[sqlx(type_name = "capability")]
 #[sqlx(rename_all = "lowercase")]
-pub(crate) enum KeyType {
-    Publisher,
-    Subscriber,
+pub(crate) enum Capability {
+    Publish,
+    Subscribe,
+    Presence,
+    History,
+}
+
+impl PgHasArrayType for Capability {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_capability")
+    }
+}
+
+/// The capabilities that a key has on a channel.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Grant {
+    pub(crate) channel_id: Uuid,
+    pub(crate) capabilities: Vec<Capability>,
+}
+
+/// The capabilities that a key has on all channels whose name matches a pattern, including the
+/// channels created after the grant.
+///
+/// In the pattern, `*` matches any sequence of characters.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PatternGrant {
+    pub(crate) pattern: String,
+    pub(crate) capabilities: Vec<Capability>,
+}
+
+/// All the grants of a key.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Grants {
+    pub(crate) channels: Vec<Grant>,
+    pub(crate) patterns: Vec<PatternGrant>,
 }
 
 pub(crate) struct Secret(String);
@@ -42,78 +81,648 @@ impl AsRef<str> for Secret {
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct Key {
     pub(crate) id: Uuid,
-    r#type: KeyType,
+    /// The capabilities that the key can be granted on channels.
+    capabilities: Vec<Capability>,
     #[serde(skip_serializing)]
     hash: String,
+    not_before: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    /// The hash of the secret the key had before its last rotation.
+    #[serde(skip_serializing)]
+    previous_hash: Option<String>,
+    /// When the secret the key had before its last rotation stops being valid.
+    previous_secret_expires_at: Option<DateTime<Utc>>,
+    name: Option<String>,
+    description: Option<String>,
+    labels: Vec<String>,
+    created_at: DateTime<Utc>,
+    /// The user who created the key.
+    created_by: Option<Uuid>,
+    /// When the key was last used, updated periodically.
+    last_used_at: Option<DateTime<Utc>>,
+    /// How many messages the key published, updated periodically.
+    publish_count: i64,
+    /// How many subscriptions the key opened, updated periodically.
+    subscribe_count: i64,
+    /// Maximum number of messages the key can publish per second.
+    pub(crate) publish_rate_limit: Option<f64>,
+    /// Maximum number of bytes the key can publish per second.
+    pub(crate) publish_byte_rate_limit: Option<f64>,
+    /// Maximum number of messages the key can publish per day.
+    daily_quota: Option<i64>,
+    /// Maximum number of messages the key can publish per month.
+    monthly_quota: Option<i64>,
+    /// How many messages the key published on `quota_day`.
+    daily_quota_used: i64,
+    /// How many messages the key published in the month starting on `quota_month`.
+    monthly_quota_used: i64,
+    quota_day: NaiveDate,
+    quota_month: NaiveDate,
+    /// The origins the key can be used from, any if empty.
+    allowed_origins: Vec<String>,
+    /// The IP ranges the key can be used from, any if empty.
+    allowed_ips: Vec<IpNetwork>,
+    pub(crate) project_id: Uuid,
+}
+
+/// The descriptive properties of a key.
+#[derive(Debug)]
+pub(crate) struct KeyMetadata {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) labels: Vec<String>,
+}
+
+/// The publish rate limits and quotas of a key, all optional.
+#[derive(Debug)]
+pub(crate) struct KeyLimits {
+    pub(crate) publish_rate_limit: Option<f64>,
+    pub(crate) publish_byte_rate_limit: Option<f64>,
+    pub(crate) daily_quota: Option<i64>,
+    pub(crate) monthly_quota: Option<i64>,
+}
+
+/// Where a key can be used from.
+#[derive(Debug)]
+pub(crate) struct KeyAllowlists {
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) allowed_ips: Vec<IpNetwork>,
 }
 
 /// CRUD
 impl Key {
-    /// Create a new key.
+    /// Create a new key in a project, with access to the channels.
     ///
     /// Returns the key and its secret. The secret will only be returned once, when the key is
     /// created.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new(
         pool: &PgPool,
-        r#type: KeyType,
+        created_by: &User,
+        project_id: Uuid,
+        metadata: KeyMetadata,
+        limits: KeyLimits,
+        allowlists: KeyAllowlists,
+        capabilities: Vec<Capability>,
         channel_ids: Vec<Uuid>,
+        not_before: Option<DateTime<Utc>>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(Self, Secret)> {
+        let mut transaction = pool.begin().await?;
+        Self::lock_channels(&mut transaction, project_id, &channel_ids, created_by).await?;
         let secret = Secret::new(pool).await?;
         let key = sqlx::query_as!(
             Self,
             r#"
-            INSERT INTO "Key" (type, hash)
-                VALUES ($1, crypt($2, gen_salt('md5')))
-            RETURNING id, type as "type: _", hash
+            INSERT INTO "Key" (
+                capabilities, hash, not_before, expires_at, name, description, labels, created_by,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                allowed_origins, allowed_ips, project_id
+            )
+                VALUES (
+                    $1, crypt($2, gen_salt('md5')), $3, $4, $5, $6, $7, $8, $9, $10, $11, $12,
+                    $13, $14, $15
+                )
+            RETURNING id, capabilities as "capabilities: _", hash, not_before, expires_at, previous_hash,
+                previous_secret_expires_at, name, description,
+                labels, created_at, created_by, last_used_at, publish_count, subscribe_count,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                daily_quota_used, monthly_quota_used, quota_day, quota_month, allowed_origins,
+                allowed_ips, project_id
             "#,
-            r#type as KeyType,
+            &capabilities as &[Capability],
             secret.as_ref(),
+            not_before,
+            expires_at,
+            metadata.name,
+            metadata.description,
+            &metadata.labels,
+            created_by.id,
+            limits.publish_rate_limit,
+            limits.publish_byte_rate_limit,
+            limits.daily_quota,
+            limits.monthly_quota,
+            &allowlists.allowed_origins,
+            &allowlists.allowed_ips,
+            project_id,
         )
-        .fetch_one(pool)
+        .fetch_one(&mut transaction)
         .await?;
-        let mut query =
-            QueryBuilder::<Postgres>::new(r#"INSERT INTO "Access" (key_id, channel_id) "#);
-        query.push_values(channel_ids, |mut b, channel_id| {
-            b.push_bind(key.id).push_bind(channel_id);
-        });
-        query.build().execute(pool).await?;
+        if !channel_ids.is_empty() {
+            let mut query = QueryBuilder::<Postgres>::new(
+                r#"INSERT INTO "Access" (key_id, channel_id, capabilities) "#,
+            );
+            query.push_values(unique(channel_ids), |mut b, channel_id| {
+                b.push_bind(key.id)
+                    .push_bind(channel_id)
+                    .push_bind(&key.capabilities);
+            });
+            query.build().execute(&mut transaction).await?;
+        }
+        transaction.commit().await?;
         Ok((key, secret))
     }
 
+    /// Check that all the channels exist in the project and that the user can see them, and
+    /// prevent them from being deleted until the end of the transaction.
+    async fn lock_channels(
+        transaction: &mut Transaction<'_, Postgres>,
+        project_id: Uuid,
+        channel_ids: &[Uuid],
+        user: &User,
+    ) -> Result<()> {
+        let found = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM "Channel"
+                WHERE id = ANY($1) AND project_id = $3 AND (
+                    user_manages($2, created_by, project_id)
+                        OR id IN (SELECT channel_id FROM "ChannelCollaborator" WHERE user_id = $2)
+                )
+            FOR KEY SHARE
+            "#,
+            channel_ids,
+            user.id,
+            project_id,
+        )
+        .fetch_all(transaction)
+        .await?;
+        let missing: Vec<Uuid> = channel_ids
+            .iter()
+            .filter(|channel_id| !found.contains(channel_id))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnknownChannels(missing))
+        }
+    }
+
     /// Get a key.
     pub(crate) async fn get(pool: &PgPool, id: Uuid) -> Result<Self> {
-        Ok(sqlx::query_as!(
+        sqlx::query_as!(
             Self,
             r#"
-            SELECT id, type as "type: _", hash FROM "Key"
+            SELECT id, capabilities as "capabilities: _", hash, not_before, expires_at, previous_hash,
+                previous_secret_expires_at, name, description,
+                labels, created_at, created_by, last_used_at, publish_count, subscribe_count,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                daily_quota_used, monthly_quota_used, quota_day, quota_month, allowed_origins,
+                allowed_ips, project_id
+            FROM "Key"
                 WHERE id = $1
             "#,
             id,
         )
         .fetch_optional(pool)
         .await?
-        .ok_or(Error::NotFound)?)
+        .ok_or(Error::NotFound)
     }
 
-    /// Check that the secret is the key's.
+    /// Check that the secret is the key's, or its previous one if it is still valid.
     async fn check_secret(&self, pool: &PgPool, secret: &Secret) -> Result<bool> {
-        Ok(
-            sqlx::query_scalar!(r#"SELECT $1 = crypt($2, $1)"#, self.hash, secret.as_ref())
-                .fetch_one(pool)
-                .await
-                .map(|option| option.expect("NULL from SELECT scalar"))?,
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT $1 = crypt($2, $1)
+                OR COALESCE($3 = crypt($2, $3) AND $4 > now(), false)
+            "#,
+            self.hash,
+            secret.as_ref(),
+            self.previous_hash,
+            self.previous_secret_expires_at,
+        )
+        .fetch_one(pool)
+        .await
+        .map(|option| option.expect("NULL from SELECT scalar"))?)
+    }
+
+    /// Replace the key's secret with a new one.
+    ///
+    /// If a grace period is given, the old secret stays valid for that many seconds. Returns the new
+    /// secret, which will only be returned once.
+    pub(crate) async fn rotate(
+        &mut self,
+        pool: &PgPool,
+        grace_period: Option<i32>,
+    ) -> Result<Secret> {
+        let secret = Secret::new(pool).await?;
+        *self = sqlx::query_as!(
+            Self,
+            r#"
+            UPDATE "Key"
+                SET hash = crypt($2, gen_salt('md5')),
+                    previous_hash = CASE WHEN $3::int IS NULL THEN NULL ELSE hash END,
+                    previous_secret_expires_at = now() + make_interval(secs => $3)
+                WHERE id = $1
+            RETURNING id, capabilities as "capabilities: _", hash, not_before, expires_at, previous_hash,
+                previous_secret_expires_at, name, description,
+                labels, created_at, created_by, last_used_at, publish_count, subscribe_count,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                daily_quota_used, monthly_quota_used, quota_day, quota_month, allowed_origins,
+                allowed_ips, project_id
+            "#,
+            self.id,
+            secret.as_ref(),
+            grace_period,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(secret)
+    }
+
+    /// Get a key, if the user can see it.
+    pub(crate) async fn get_visible(pool: &PgPool, id: Uuid, user: &User) -> Result<Self> {
+        let key = Self::get(pool, id).await?;
+        if key.is_visible_to(pool, user).await? {
+            Ok(key)
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    /// Get all keys that the user can see, optionally only those of a project or with a label.
+    pub(crate) async fn get_all(
+        pool: &PgPool,
+        user: &User,
+        project_id: Option<Uuid>,
+        label: Option<&str>,
+    ) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, capabilities as "capabilities: _", hash, not_before, expires_at, previous_hash,
+                previous_secret_expires_at, name, description,
+                labels, created_at, created_by, last_used_at, publish_count, subscribe_count,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                daily_quota_used, monthly_quota_used, quota_day, quota_month, allowed_origins,
+                allowed_ips, project_id
+            FROM "Key"
+                WHERE ($1::text IS NULL OR $1 = ANY(labels))
+                    AND ($3::uuid IS NULL OR project_id = $3)
+                    AND (
+                        user_manages($2, created_by, project_id)
+                            OR id IN (SELECT key_id FROM "KeyCollaborator" WHERE user_id = $2)
+                    )
+            "#,
+            label,
+            user.id,
+            project_id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Get the keys that the user can see and that have access to a channel.
+    pub(crate) async fn get_from_channel(
+        pool: &PgPool,
+        channel: &Channel,
+        user: &User,
+    ) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, capabilities as "capabilities: _", hash, not_before, expires_at,
+                previous_hash, previous_secret_expires_at, name, description, labels, created_at,
+                created_by, last_used_at, publish_count, subscribe_count,
+                publish_rate_limit, publish_byte_rate_limit, daily_quota, monthly_quota,
+                daily_quota_used, monthly_quota_used, quota_day, quota_month, allowed_origins,
+                allowed_ips, project_id
+            FROM "Key"
+                WHERE project_id = $4 AND (
+                    id IN (SELECT key_id FROM "Access" WHERE channel_id = $1)
+                        OR id IN (
+                            SELECT key_id FROM "PatternAccess"
+                                WHERE channel_name_matches($2, pattern)
+                        )
+                ) AND (
+                    user_manages($3, created_by, project_id)
+                        OR id IN (SELECT key_id FROM "KeyCollaborator" WHERE user_id = $3)
+                )
+            "#,
+            channel.id,
+            channel.name,
+            user.id,
+            channel.project_id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Count the keys that have access to a channel.
+    pub(crate) async fn count_from_channel(pool: &PgPool, channel: &Channel) -> Result<i64> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) FROM "Key"
+                WHERE project_id = $3 AND (
+                    id IN (SELECT key_id FROM "Access" WHERE channel_id = $1)
+                        OR id IN (
+                            SELECT key_id FROM "PatternAccess"
+                                WHERE channel_name_matches($2, pattern)
+                        )
+                )
+            "#,
+            channel.id,
+            channel.name,
+            channel.project_id,
+        )
+        .fetch_one(pool)
+        .await?
+        .expect("NULL from SELECT scalar"))
+    }
+
+    /// Add usage statistics to keys, collected since the last time this was called.
+    ///
+    /// Database errors are returned rather than panicking, for the background job to retry.
+    pub(crate) async fn record_usage(pool: &PgPool, usage: &KeyUsage) -> sqlx::Result<()> {
+        let mut ids = Vec::new();
+        let mut last_used_at = Vec::new();
+        let mut publish_counts = Vec::new();
+        let mut subscribe_counts = Vec::new();
+        for (&id, key_usage) in usage.iter() {
+            ids.push(id);
+            last_used_at.push(key_usage.last_used_at);
+            publish_counts.push(key_usage.publish_count);
+            subscribe_counts.push(key_usage.subscribe_count);
+        }
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET last_used_at = GREATEST("Key".last_used_at, usage.last_used_at),
+                    publish_count = "Key".publish_count + usage.publish_count,
+                    subscribe_count = "Key".subscribe_count + usage.subscribe_count
+                FROM UNNEST($1::uuid[], $2::timestamptz[], $3::bigint[], $4::bigint[])
+                    AS usage(id, last_used_at, publish_count, subscribe_count)
+                WHERE "Key".id = usage.id
+            "#,
+            &ids,
+            &last_used_at,
+            &publish_counts,
+            &subscribe_counts,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Set the key's publish rate limits and quotas.
+    pub(crate) async fn set_limits(&mut self, pool: &PgPool, limits: KeyLimits) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET publish_rate_limit = $2,
+                    publish_byte_rate_limit = $3,
+                    daily_quota = $4,
+                    monthly_quota = $5
+                WHERE id = $1
+            "#,
+            self.id,
+            limits.publish_rate_limit,
+            limits.publish_byte_rate_limit,
+            limits.daily_quota,
+            limits.monthly_quota,
+        )
+        .execute(pool)
+        .await?;
+        self.publish_rate_limit = limits.publish_rate_limit;
+        self.publish_byte_rate_limit = limits.publish_byte_rate_limit;
+        self.daily_quota = limits.daily_quota;
+        self.monthly_quota = limits.monthly_quota;
+        Ok(())
+    }
+
+    /// Set where the key can be used from.
+    pub(crate) async fn set_allowlists(
+        &mut self,
+        pool: &PgPool,
+        allowlists: KeyAllowlists,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET allowed_origins = $2,
+                    allowed_ips = $3
+                WHERE id = $1
+            "#,
+            self.id,
+            &allowlists.allowed_origins,
+            &allowlists.allowed_ips,
+        )
+        .execute(pool)
+        .await?;
+        self.allowed_origins = allowlists.allowed_origins;
+        self.allowed_ips = allowlists.allowed_ips;
+        Ok(())
+    }
+
+    /// Count a published message against the key's daily and monthly quotas, if it has any.
+    pub(crate) async fn consume_quota(&self, pool: &PgPool) -> Result<()> {
+        if self.daily_quota.is_none() && self.monthly_quota.is_none() {
+            return Ok(());
+        }
+        let consumed = sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET daily_quota_used =
+                        CASE WHEN quota_day = CURRENT_DATE THEN daily_quota_used ELSE 0 END + 1,
+                    monthly_quota_used =
+                        CASE WHEN quota_month = date_trunc('month', CURRENT_DATE)
+                            THEN monthly_quota_used ELSE 0 END + 1,
+                    quota_day = CURRENT_DATE,
+                    quota_month = date_trunc('month', CURRENT_DATE)
+                WHERE id = $1
+                    AND (daily_quota IS NULL OR daily_quota >
+                        CASE WHEN quota_day = CURRENT_DATE THEN daily_quota_used ELSE 0 END)
+                    AND (monthly_quota IS NULL OR monthly_quota >
+                        CASE WHEN quota_month = date_trunc('month', CURRENT_DATE)
+                            THEN monthly_quota_used ELSE 0 END)
+            "#,
+            self.id,
+        )
+        .execute(pool)
+        .await?
+        .rows_affected()
+            == 1;
+        if consumed {
+            return Ok(());
+        }
+        // wait for the end of the month if the monthly quota is exhausted, of the day otherwise
+        let retry_after = sqlx::query_scalar!(
+            r#"
+            SELECT EXTRACT(EPOCH FROM
+                CASE WHEN monthly_quota <= monthly_quota_used
+                        AND quota_month = date_trunc('month', CURRENT_DATE)
+                    THEN date_trunc('month', now()) + interval '1 month'
+                    ELSE date_trunc('day', now()) + interval '1 day'
+                END - now()
+            )::bigint AS "retry_after!"
+            FROM "Key"
+                WHERE id = $1
+            "#,
+            self.id,
         )
+        .fetch_one(pool)
+        .await?;
+        Err(Error::QuotaExceeded(Duration::from_secs(
+            retry_after.max(1) as u64,
+        )))
+    }
+
+    /// Reset the quota counters of the keys that did not publish since the previous day or month.
+    ///
+    /// Database errors are returned rather than panicking, for the background job to retry.
+    pub(crate) async fn reset_quotas(pool: &PgPool) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET daily_quota_used = 0,
+                    quota_day = CURRENT_DATE
+                WHERE quota_day <> CURRENT_DATE
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET monthly_quota_used = 0,
+                    quota_month = date_trunc('month', CURRENT_DATE)
+                WHERE quota_month <> date_trunc('month', CURRENT_DATE)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete all keys that expired before `expired_before`.
+    ///
+    /// Returns the number of deleted keys. Database errors are returned rather than panicking, for
+    /// the background job to retry.
+    pub(crate) async fn delete_expired(
+        pool: &PgPool,
+        expired_before: DateTime<Utc>,
+    ) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM "Key"
+                WHERE expires_at < $1
+            "#,
+            expired_before,
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+
+    /// Get the key's grants.
+    pub(crate) async fn grants(&self, pool: &PgPool) -> Result<Grants> {
+        let channels = sqlx::query_as!(
+            Grant,
+            r#"
+            SELECT channel_id, capabilities as "capabilities: _" FROM "Access"
+                WHERE key_id = $1
+            "#,
+            self.id,
+        )
+        .fetch_all(pool)
+        .await?;
+        let patterns = sqlx::query_as!(
+            PatternGrant,
+            r#"
+            SELECT pattern, capabilities as "capabilities: _" FROM "PatternAccess"
+                WHERE key_id = $1
+            "#,
+            self.id,
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(Grants { channels, patterns })
     }
 
-    /// Get all keys.
-    pub(crate) async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
-        Ok(
-            sqlx::query_as!(Self, r#"SELECT id, type as "type: _", hash FROM "Key""#)
-                .fetch_all(pool)
-                .await?,
+    /// Grant capabilities on channels and patterns to the key, replacing the ones it had on those
+    /// channels and patterns, and revoke all its capabilities on other channels and patterns.
+    ///
+    /// The key must hold all the granted capabilities, and the user must be able to see the
+    /// channels. Returns the key's grants after the update.
+    pub(crate) async fn update_grants(
+        &self,
+        pool: &PgPool,
+        user: &User,
+        grants: &Grants,
+        revoked_channel_ids: &[Uuid],
+        revoked_patterns: &[String],
+    ) -> Result<Grants> {
+        if let Some(&capability) = grants
+            .channels
+            .iter()
+            .flat_map(|grant| &grant.capabilities)
+            .chain(grants.patterns.iter().flat_map(|grant| &grant.capabilities))
+            .find(|capability| !self.capabilities.contains(capability))
+        {
+            return Err(Error::CapabilityNotHeld(capability));
+        }
+        let mut transaction = pool.begin().await?;
+        let channel_ids: Vec<Uuid> = grants
+            .channels
+            .iter()
+            .map(|grant| grant.channel_id)
+            .collect();
+        Self::lock_channels(&mut transaction, self.project_id, &channel_ids, user).await?;
+        for grant in &grants.channels {
+            sqlx::query!(
+                r#"
+                INSERT INTO "Access" (key_id, channel_id, capabilities)
+                    VALUES ($1, $2, $3)
+                ON CONFLICT (key_id, channel_id) DO UPDATE
+                    SET capabilities = EXCLUDED.capabilities
+                "#,
+                self.id,
+                grant.channel_id,
+                &grant.capabilities as &[Capability],
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM "Access"
+                WHERE key_id = $1 AND channel_id = ANY($2)
+            "#,
+            self.id,
+            revoked_channel_ids,
         )
+        .execute(&mut transaction)
+        .await?;
+        for grant in &grants.patterns {
+            sqlx::query!(
+                r#"
+                INSERT INTO "PatternAccess" (key_id, pattern, capabilities)
+                    VALUES ($1, $2, $3)
+                ON CONFLICT (key_id, pattern) DO UPDATE
+                    SET capabilities = EXCLUDED.capabilities
+                "#,
+                self.id,
+                grant.pattern,
+                &grant.capabilities as &[Capability],
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM "PatternAccess"
+                WHERE key_id = $1 AND pattern = ANY($2)
+            "#,
+            self.id,
+            revoked_patterns,
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        self.grants(pool).await
     }
 
     /// Delete the key.
@@ -132,55 +741,205 @@ impl Key {
     }
 }
 
+/// Ownership
+impl Key {
+    /// Returns whether the user created the key or is an ancestor of its creator.
+    ///
+    /// Only those users can share the key with others.
+    pub(crate) async fn is_managed_by(&self, pool: &PgPool, user: &User) -> Result<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT user_manages($1, $2, $3)"#,
+            user.id,
+            self.created_by,
+            self.project_id,
+        )
+        .fetch_one(pool)
+        .await
+        .map(|option| option.expect("NULL from SELECT scalar"))?)
+    }
+
+    /// Returns whether the user manages the key or it was shared with them.
+    pub(crate) async fn is_visible_to(&self, pool: &PgPool, user: &User) -> Result<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT user_manages($1, $2, $3) OR EXISTS (
+                SELECT FROM "KeyCollaborator"
+                    WHERE key_id = $4 AND user_id = $1
+            )
+            "#,
+            user.id,
+            self.created_by,
+            self.project_id,
+            self.id,
+        )
+        .fetch_one(pool)
+        .await
+        .map(|option| option.expect("NULL from SELECT scalar"))?)
+    }
+
+    /// Get the ids of the users the key is shared with.
+    pub(crate) async fn collaborators(&self, pool: &PgPool) -> Result<Vec<Uuid>> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT user_id FROM "KeyCollaborator"
+                WHERE key_id = $1
+            "#,
+            self.id,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Share the key with a user.
+    pub(crate) async fn share(&self, pool: &PgPool, user: &User) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO "KeyCollaborator" (key_id, user_id)
+                VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            self.id,
+            user.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Stop sharing the key with a user.
+    pub(crate) async fn unshare(&self, pool: &PgPool, user_id: Uuid) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "KeyCollaborator"
+                WHERE key_id = $1 AND user_id = $2
+            "#,
+            self.id,
+            user_id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
 impl Key {
-    /// Returns whether the key is a publisher key.
-    pub(crate) fn is_publisher(&self) -> bool {
-        matches!(self.r#type, KeyType::Publisher)
+    /// The capabilities that the key can be granted on channels.
+    pub(crate) fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Check that the key is valid at the current time.
+    fn check_validity_period(&self) -> Result<()> {
+        let now = Utc::now();
+        if self.not_before.is_some_and(|not_before| now < not_before) {
+            Err(Error::NotYetValid)
+        } else if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            Err(Error::Expired)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that the request comes from an allowed origin, if the key restricts origins.
+    ///
+    /// Requests without an `Origin` header are rejected by keys that restrict origins.
+    fn check_origin(&self, origin: Option<&str>) -> Result<()> {
+        if self.allowed_origins.is_empty()
+            || origin.is_some_and(|origin| self.allowed_origins.iter().any(|o| o == origin))
+        {
+            Ok(())
+        } else {
+            Err(Error::OriginNotAllowed)
+        }
+    }
+
+    /// Check that the request comes from an allowed IP, if the key restricts IPs.
+    fn check_ip(&self, ip: IpAddr) -> Result<()> {
+        if self.allowed_ips.is_empty()
+            || self.allowed_ips.iter().any(|network| network.contains(ip))
+        {
+            Ok(())
+        } else {
+            Err(Error::IpNotAllowed)
+        }
     }
 
-    /// Returns whether the key is a subscriber key.
-    pub(crate) fn is_subscriber(&self) -> bool {
-        matches!(self.r#type, KeyType::Subscriber)
+    /// Returns whether the key restricts the origins it can be used from.
+    pub(crate) fn restricts_origins(&self) -> bool {
+        !self.allowed_origins.is_empty()
     }
 
-    /// Returns whether the key authorizes the channel.
-    pub(crate) async fn authorizes(&self, pool: &PgPool, channel: &Channel) -> Result<bool> {
+    /// Returns whether the key has the capability on the channel, either directly or through a
+    /// pattern.
+    ///
+    /// Keys never authorize the channels of other projects.
+    pub(crate) async fn authorizes(
+        &self,
+        pool: &PgPool,
+        channel: &Channel,
+        capability: Capability,
+    ) -> Result<bool> {
+        if channel.project_id != self.project_id {
+            return Ok(false);
+        }
         Ok(sqlx::query_scalar!(
             r#"
-            SELECT COUNT(*) FROM "Access"
-                WHERE key_id = $1 AND channel_id = $2
+            SELECT EXISTS (
+                SELECT FROM "Access"
+                    WHERE key_id = $1 AND channel_id = $2 AND $3 = ANY(capabilities)
+            ) OR EXISTS (
+                SELECT FROM "PatternAccess"
+                    WHERE key_id = $1
+                        AND channel_name_matches($4, pattern)
+                        AND $3 = ANY(capabilities)
+            )
             "#,
             self.id,
             channel.id,
+            capability as Capability,
+            channel.name,
         )
         .fetch_one(pool)
         .await?
-        .expect("NULL from SELECT scalar")
-            == 1)
+        .expect("NULL from SELECT scalar"))
     }
 }
 
-#[async_trait]
-impl<S> FromRequestParts<S> for Key
-where
-    S: Send + Sync,
-    SharedState: FromRef<S>,
-{
-    type Rejection = Error;
-
-    /// Parse the Authorization header, expecting a token containing the key's id and secret
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
-        let authorization_header =
-            TypedHeader::<Authorization<Basic>>::from_request_parts(parts, state).await?;
-        let id =
-            Uuid::try_parse(authorization_header.username()).map_err(|_| Error::InvalidKeyId)?;
-        let secret = Secret(authorization_header.password().to_owned());
+impl Key {
+    /// Parse a token of the form `{id};{secret}`.
+    pub(crate) fn parse_token(token: &str) -> Result<(Uuid, Secret)> {
+        let (id, secret) = token.split_once(';').ok_or(Error::MalformedToken)?;
+        Ok((Self::parse_id(id)?, Self::parse_secret(secret)?))
+    }
 
-        let state = SharedState::from_ref(state);
+    /// Parse a key id.
+    pub(crate) fn parse_id(id: &str) -> Result<Uuid> {
+        Uuid::try_parse(id).map_err(|_| Error::InvalidKeyId)
+    }
 
-        let key = Key::get(&state.read().await.pool, id).await?;
+    /// Parse a secret, which must not be empty.
+    pub(crate) fn parse_secret(secret: &str) -> Result<Secret> {
+        if secret.is_empty() {
+            Err(Error::MissingSecretKey)
+        } else {
+            Ok(Secret(secret.to_owned()))
+        }
+    }
 
-        if key.check_secret(&state.read().await.pool, &secret).await? {
+    /// Get a key, checking its secret, that it is valid, and that it can be used from the
+    /// request's origin and IP.
+    pub(crate) async fn authenticate(
+        pool: &PgPool,
+        id: Uuid,
+        secret: Secret,
+        origin: Option<&str>,
+        ip: IpAddr,
+    ) -> Result<Self> {
+        let key = Key::get(pool, id).await?;
+        if key.check_secret(pool, &secret).await? {
+            key.check_validity_period()?;
+            key.check_origin(origin)?;
+            key.check_ip(ip)?;
             Ok(key)
         } else {
             Err(Error::InvalidSecretKey)
@@ -188,11 +947,31 @@ where
     }
 }
 
+/// The channel ids without repetitions, each of which can only be inserted once.
+fn unique(channel_ids: Vec<Uuid>) -> BTreeSet<Uuid> {
+    channel_ids.into_iter().collect()
+}
+
 pub(crate) mod error {
-    use axum::extract::rejection::TypedHeaderRejection;
+    use std::time::Duration;
+
+    use axum::extract::rejection::{QueryRejection, TypedHeaderRejection};
+    use axum::http::header::RETRY_AFTER;
     use axum::response::IntoResponse;
+    use axum::Json;
     use hyper::StatusCode;
+    use serde::Serialize;
     use tracing::{debug, error};
+    use uuid::Uuid;
+
+    use super::Capability;
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct UnknownChannels<'a> {
+        message: String,
+        channel_ids: &'a [Uuid],
+    }
 
     pub(crate) type Result<T> = std::result::Result<T, Error>;
 
@@ -200,16 +979,52 @@ pub(crate) mod error {
     pub(crate) enum Error {
         #[error(transparent)]
         TypedHeaderRejection(#[from] TypedHeaderRejection),
-        #[error("Invalid API key ID")]
+        #[error(transparent)]
+        QueryRejection(#[from] QueryRejection),
+        #[error("Malformed token, expected `{{id}};{{secret}}`")]
+        MalformedToken,
+        #[error("Invalid API key ID, expected a UUID")]
         InvalidKeyId,
+        #[error("Missing secret key")]
+        MissingSecretKey,
         #[error("Invalid secret key")]
         InvalidSecretKey,
         #[error("Key not found")]
         NotFound,
+        #[error("Key is not valid yet")]
+        NotYetValid,
+        #[error("Key has expired")]
+        Expired,
+        #[error("Key validity period must end after it starts")]
+        InvalidValidityPeriod,
+        #[error("Key does not hold the {0:?} capability")]
+        CapabilityNotHeld(Capability),
+        #[error("Unknown channels")]
+        UnknownChannels(Vec<Uuid>),
+        #[error("Invalid limits, rate limits must be positive and quotas must not be negative")]
+        InvalidLimits,
+        #[error("Publish quota exceeded")]
+        QuotaExceeded(Duration),
+        #[error("Key cannot be used from this origin")]
+        OriginNotAllowed,
+        #[error("Key cannot be used from this IP address")]
+        IpNotAllowed,
     }
 
     impl From<sqlx::Error> for Error {
         fn from(error: sqlx::Error) -> Self {
+            if let Some(database_error) = error.as_database_error() {
+                match database_error.constraint() {
+                    Some("Key_validity_period_check") => return Self::InvalidValidityPeriod,
+                    Some(
+                        "Key_publish_rate_limit_check"
+                        | "Key_publish_byte_rate_limit_check"
+                        | "Key_daily_quota_check"
+                        | "Key_monthly_quota_check",
+                    ) => return Self::InvalidLimits,
+                    _ => {}
+                }
+            }
             error!(?error);
             panic!("unknown database error");
         }
@@ -220,12 +1035,61 @@ pub(crate) mod error {
             debug!(?self);
             match self {
                 Error::TypedHeaderRejection(error) => error.into_response(),
+                Error::QueryRejection(error) => error.into_response(),
+                Error::MalformedToken => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
                 Error::InvalidKeyId => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+                Error::MissingSecretKey => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
                 Error::InvalidSecretKey => {
                     (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
                 }
                 Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+                Error::NotYetValid => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+                Error::Expired => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+                Error::InvalidValidityPeriod => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+                }
+                Error::CapabilityNotHeld(_) => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+                }
+                Error::UnknownChannels(ref channel_ids) => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(UnknownChannels {
+                        message: self.to_string(),
+                        channel_ids,
+                    }),
+                )
+                    .into_response(),
+                Error::InvalidLimits => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+                Error::OriginNotAllowed => {
+                    (StatusCode::FORBIDDEN, self.to_string()).into_response()
+                }
+                Error::IpNotAllowed => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+                Error::QuotaExceeded(retry_after) => (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after.as_secs().to_string())],
+                    self.to_string(),
+                )
+                    .into_response(),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_drops_repeated_channels() {
+        let channel_id = Uuid::from_u128(1);
+        let other_channel_id = Uuid::from_u128(2);
+        assert_eq!(
+            unique(vec![channel_id, other_channel_id, channel_id]),
+            BTreeSet::from([channel_id, other_channel_id]),
+        );
+    }
+}
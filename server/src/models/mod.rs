@@ -1,3 +1,8 @@
+pub(crate) mod access_token;
 pub(crate) mod channel;
+pub(crate) mod invitation;
 pub(crate) mod key;
+pub(crate) mod project;
+pub(crate) mod session;
+pub(crate) mod token;
 pub(crate) mod user;
@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use self::error::{Error, Result};
+
+/// Name of the project that existing channels and keys were moved to when projects were added.
+pub(crate) const DEFAULT_PROJECT: &str = "default";
+
+/// A tenant of the instance, that owns channels, keys and users.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Project {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    created_at: DateTime<Utc>,
+}
+
+/// CRUD
+impl Project {
+    /// Create a new project.
+    pub(crate) async fn new(pool: &PgPool, name: &str) -> Result<Self> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"
+            INSERT INTO "Project" (name)
+                VALUES ($1)
+            RETURNING *
+            "#,
+            name,
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
+    /// Get a project.
+    pub(crate) async fn get(pool: &PgPool, id: Uuid) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT * FROM "Project"
+                WHERE id = $1
+            "#,
+            id,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Get a project by its name.
+    pub(crate) async fn get_by_name(pool: &PgPool, name: &str) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT * FROM "Project"
+                WHERE name = $1
+            "#,
+            name,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::NotFound)
+    }
+
+    /// Get all projects.
+    pub(crate) async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(Self, r#"SELECT * FROM "Project""#)
+            .fetch_all(pool)
+            .await?)
+    }
+
+    /// Delete the project, with all its channels, keys and users.
+    pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "Project"
+                WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+pub(crate) mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::{debug, error};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error("Project not found")]
+        NotFound,
+        #[error("Duplicate project name")]
+        DuplicateName,
+    }
+
+    impl From<sqlx::Error> for Error {
+        fn from(error: sqlx::Error) -> Self {
+            if let Some(database_error) = error.as_database_error() {
+                if database_error.constraint() == Some("Project_name_key") {
+                    return Self::DuplicateName;
+                }
+            }
+            error!(?error);
+            panic!("unknown database error");
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+                Error::DuplicateName => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use axum::extract::{FromRef, FromRequestParts};
+use axum::headers::authorization::Bearer;
+use axum::headers::{Authorization, Cookie};
+use axum::http::request::Parts;
+use axum::{async_trait, TypedHeader};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use self::error::{Error, Result};
+use crate::config::CONFIG;
+use crate::models::user::User;
+use crate::state::SharedState;
+
+/// Name of the cookie that can carry a session token.
+pub(crate) const SESSION_COOKIE: &str = "mercury_session";
+
+pub(crate) struct SessionToken(String);
+
+impl AsRef<str> for SessionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A logged in user's session.
+///
+/// Only a hash of the session's token is stored.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Session {
+    pub(crate) id: Uuid,
+    pub(crate) user_id: Uuid,
+    created_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+}
+
+/// CRUD
+impl Session {
+    /// Create a new session for the user, valid for `session_lifetime_hours` hours.
+    ///
+    /// Returns the session and its token. The token will only be returned once, when the session
+    /// is created.
+    pub(crate) async fn new(pool: &PgPool, user: &User) -> Result<(Self, SessionToken)> {
+        let token = sqlx::query_scalar!(r#"SELECT encode(gen_random_bytes(32), 'hex')"#)
+            .fetch_one(pool)
+            .await?
+            .expect("NULL from SELECT scalar");
+        let session = sqlx::query_as!(
+            Self,
+            r#"
+            INSERT INTO "Session" (user_id, token_hash, expires_at)
+                VALUES ($1, digest($2, 'sha256'), now() + make_interval(hours => $3))
+            RETURNING id, user_id, created_at, expires_at
+            "#,
+            user.id,
+            token,
+            CONFIG.session_lifetime_hours,
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok((session, SessionToken(token)))
+    }
+
+    /// Get an unexpired session by its token.
+    pub(crate) async fn get_by_token(pool: &PgPool, token: &str) -> Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, user_id, created_at, expires_at FROM "Session"
+                WHERE token_hash = digest($1, 'sha256') AND expires_at > now()
+            "#,
+            token,
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(Error::InvalidSession)
+    }
+
+    /// Delete all the sessions of a user, except one.
+    pub(crate) async fn delete_others(
+        pool: &PgPool,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "Session"
+                WHERE user_id = $1 AND id IS DISTINCT FROM $2
+            "#,
+            user_id,
+            except,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete all expired sessions.
+    ///
+    /// Returns the number of deleted sessions.
+    pub(crate) async fn delete_expired(pool: &PgPool) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM "Session"
+                WHERE expires_at <= now()
+            "#,
+        )
+        .execute(pool)
+        .await?
+        .rows_affected())
+    }
+
+    /// Delete the session.
+    pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "Session"
+                WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl Session {
+    /// Get a session token from the Authorization Bearer header or from the `mercury_session`
+    /// cookie.
+    ///
+    /// Bearer tokens containing a `;` are key tokens, not session tokens.
+    pub(crate) async fn token_from_request_parts<S>(parts: &mut Parts, state: &S) -> Option<String>
+    where
+        S: Send + Sync,
+    {
+        if let Ok(TypedHeader(Authorization(bearer))) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await
+        {
+            return (!bearer.token().contains(';')).then(|| bearer.token().to_owned());
+        }
+        let cookie = Option::<TypedHeader<Cookie>>::from_request_parts(parts, state)
+            .await
+            .expect("infallible");
+        cookie.and_then(|TypedHeader(cookie)| cookie.get(SESSION_COOKIE).map(String::from))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Session
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        let token = Self::token_from_request_parts(parts, state)
+            .await
+            .ok_or(Error::MissingSession)?;
+
+        let state = SharedState::from_ref(state);
+
+        let session = Self::get_by_token(&state.read().await.pool, &token).await?;
+
+        Ok(session)
+    }
+}
+
+pub(crate) mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use tracing::{debug, error};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error("Missing session token")]
+        MissingSession,
+        #[error("Invalid or expired session")]
+        InvalidSession,
+    }
+
+    impl From<sqlx::Error> for Error {
+        fn from(error: sqlx::Error) -> Self {
+            error!(?error);
+            panic!("unknown database error");
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::MissingSession => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+                Error::InvalidSession => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+            }
+        }
+    }
+}
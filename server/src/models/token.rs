@@ -0,0 +1,155 @@
+use std::fmt;
+
+use jsonwebtoken::{
+    get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use self::error::Result;
+use crate::config::CONFIG;
+use crate::models::channel::Channel;
+
+/// The keys used to sign and verify tokens.
+pub(crate) struct TokenKeys {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl TokenKeys {
+    /// Create the keys from the configured token secret.
+    ///
+    /// If no token secret is configured, a random one is generated, and tokens will not survive a
+    /// restart of the server.
+    pub(crate) async fn new(pool: &PgPool) -> Result<Self> {
+        let secret = match &CONFIG.token_secret {
+            Some(secret) => secret.clone(),
+            None => {
+                warn!("no token secret configured, generating a random one");
+                sqlx::query_scalar!(r#"SELECT encode(gen_random_bytes(48), 'base64')"#)
+                    .fetch_one(pool)
+                    .await
+                    .map(|option| option.expect("NULL from SELECT scalar"))?
+            }
+        };
+        Ok(Self {
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+        })
+    }
+}
+
+impl fmt::Debug for TokenKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenKeys").finish_non_exhaustive()
+    }
+}
+
+/// A short-lived signed token that authorizes a subscriber on specific channels.
+///
+/// Tokens are verified statelessly, they cannot be revoked before they expire.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Token {
+    /// The user or publisher key that issued the token.
+    iss: String,
+    /// The subscriber the token was issued to.
+    pub(crate) sub: String,
+    /// Issued at, as a UNIX timestamp.
+    iat: u64,
+    /// Expiration time, as a UNIX timestamp.
+    exp: u64,
+    /// The project of the channels, none if there are none.
+    #[serde(default)]
+    pub(crate) project: Option<Uuid>,
+    /// The ids of the channels that the token authorizes.
+    channels: Vec<Uuid>,
+}
+
+impl Token {
+    /// Create a new token that expires in `expires_in` seconds.
+    pub(crate) fn new(
+        issuer: String,
+        subject: String,
+        project: Option<Uuid>,
+        channels: Vec<Uuid>,
+        expires_in: u64,
+    ) -> Self {
+        let now = get_current_timestamp();
+        Self {
+            iss: issuer,
+            sub: subject,
+            iat: now,
+            exp: now + expires_in,
+            project,
+            channels,
+        }
+    }
+
+    /// Returns whether a string looks like a signed token rather than a key token.
+    pub(crate) fn is_token(token: &str) -> bool {
+        !token.contains(';') && token.split('.').count() == 3
+    }
+
+    /// Sign the token.
+    pub(crate) fn sign(&self, keys: &TokenKeys) -> String {
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), self, &keys.encoding_key)
+            .expect("HMAC signing failed")
+    }
+
+    /// Verify the signature and the expiration time of a token.
+    pub(crate) fn verify(keys: &TokenKeys, token: &str) -> Result<Self> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        Ok(jsonwebtoken::decode::<Self>(token, &keys.decoding_key, &validation)?.claims)
+    }
+
+    /// Returns whether the token authorizes the channel.
+    pub(crate) fn authorizes(&self, channel: &Channel) -> bool {
+        self.channels.contains(&channel.id)
+    }
+}
+
+pub(crate) mod error {
+    use axum::response::IntoResponse;
+    use hyper::StatusCode;
+    use jsonwebtoken::errors::ErrorKind;
+    use tracing::{debug, error};
+
+    pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum Error {
+        #[error("Invalid token")]
+        InvalidToken,
+        #[error("Expired token")]
+        ExpiredToken,
+    }
+
+    impl From<sqlx::Error> for Error {
+        fn from(error: sqlx::Error) -> Self {
+            error!(?error);
+            panic!("unknown database error");
+        }
+    }
+
+    impl From<jsonwebtoken::errors::Error> for Error {
+        fn from(error: jsonwebtoken::errors::Error) -> Self {
+            match error.kind() {
+                ErrorKind::ExpiredSignature => Self::ExpiredToken,
+                _ => Self::InvalidToken,
+            }
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> axum::response::Response {
+            debug!(?self);
+            match self {
+                Error::InvalidToken => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+                Error::ExpiredToken => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+            }
+        }
+    }
+}
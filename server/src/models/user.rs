@@ -1,52 +1,236 @@
+use std::fmt;
+use std::net::IpAddr;
+
 use axum::extract::{FromRef, FromRequestParts};
 use axum::headers::authorization::Basic;
 use axum::headers::Authorization;
 use axum::http::request::Parts;
 use axum::{async_trait, TypedHeader};
-use serde::Serialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use totp_rs::{Algorithm, TOTP};
+use tracing::{info, warn};
 use uuid::Uuid;
+use validator::ValidationError;
 
 use self::error::{Error, Result};
+use crate::api::extract::client_ip::ClientIp;
+use crate::config::CONFIG;
+use crate::models::access_token::{AccessToken, Scope, ACCESS_TOKEN_PREFIX};
+use crate::models::session::Session;
 use crate::state::SharedState;
 
+/// A hash to check passwords against when the user does not exist.
+const DUMMY_PASSWORD_HASH: &str = "$1$JvjRXPhN$dvY7AUaid2c0ti2uVQkPe0";
+
+/// What a user is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "role")]
+#[sqlx(rename_all = "kebab-case")]
+pub(crate) enum Role {
+    Owner,
+    Admin,
+    ChannelManager,
+    KeyManager,
+    Viewer,
+}
+
+/// An action on the admin API that requires a permission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Permission {
+    ViewChannels,
+    ManageChannels,
+    ViewKeys,
+    ManageKeys,
+    ViewUsers,
+    ManageUsers,
+    /// Change own name, password, second factor and access tokens.
+    ManageAccount,
+}
+
+impl Role {
+    /// Returns whether the role has the permission.
+    pub(crate) fn has(self, permission: Permission) -> bool {
+        match self {
+            Self::Owner | Self::Admin => true,
+            Self::ChannelManager => {
+                permission != Permission::ManageKeys && permission != Permission::ManageUsers
+            }
+            Self::KeyManager => {
+                permission != Permission::ManageChannels && permission != Permission::ManageUsers
+            }
+            Self::Viewer => matches!(
+                permission,
+                Permission::ViewChannels
+                    | Permission::ViewKeys
+                    | Permission::ViewUsers
+                    | Permission::ManageAccount
+            ),
+        }
+    }
+
+    /// Returns whether a user with this role can give the other role to a user.
+    ///
+    /// Only owners can make other owners and admins.
+    pub(crate) fn can_assign(self, other: Role) -> bool {
+        match self {
+            Self::Owner => true,
+            Self::Admin => !matches!(other, Self::Owner | Self::Admin),
+            _ => false,
+        }
+    }
+}
+
 /// An application user.
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct User {
-    id: Uuid,
+    pub(crate) id: Uuid,
     name: String,
     #[serde(skip_serializing)]
     password_hash: String,
+    /// The user who created this one, or none for root users.
+    pub(crate) parent_id: Option<Uuid>,
+    pub(crate) role: Role,
+    /// The project the user belongs to, or none if they can access all projects.
+    pub(crate) project_id: Option<Uuid>,
+    /// Whether the user must change their password before using the rest of the API.
+    must_change_password: bool,
+    /// The TOTP secret, being enrolled if `totp_enabled` is false.
+    #[serde(skip_serializing)]
+    totp_secret: Option<Vec<u8>>,
+    /// Whether a TOTP code is required to authenticate with the user's password.
+    totp_enabled: bool,
+    /// The scope of the access token the user authenticated with, if any.
     #[serde(skip_serializing)]
-    pub(crate) rank: i32,
+    access_token_scope: Option<Scope>,
+}
+
+/// Leaves out the password hash and the TOTP secret, users being logged by the request handlers.
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("parent_id", &self.parent_id)
+            .field("role", &self.role)
+            .field("project_id", &self.project_id)
+            .field("must_change_password", &self.must_change_password)
+            .field("totp_enabled", &self.totp_enabled)
+            .field("access_token_scope", &self.access_token_scope)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Validate a user name, which can contain no ':' to be the label of TOTP URIs (for the validator
+/// crate).
+pub(crate) fn validate_name(name: &str) -> std::result::Result<(), ValidationError> {
+    if (4..=16).contains(&name.chars().count()) && !name.contains(':') {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Invalid name"))
+    }
+}
+
+/// Name of the header that carries a TOTP code or a recovery code along with Basic credentials.
+pub(crate) const TOTP_HEADER: &str = "x-mercury-totp";
+
+/// What an authenticator app needs to generate TOTP codes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TotpEnrolment {
+    /// The secret, in base 32.
+    secret: String,
+    /// An `otpauth://` URI, usually shown as a QR code.
+    uri: String,
 }
 
 /// CRUD
 impl User {
     /// Create a new user.
-    pub(crate) async fn new(pool: &PgPool, name: &str, password: &str, rank: i32) -> Result<Self> {
+    pub(crate) async fn new(
+        executor: impl PgExecutor<'_>,
+        name: &str,
+        password: &str,
+        parent: &User,
+        role: Role,
+        project_id: Option<Uuid>,
+    ) -> Result<Self> {
         Ok(sqlx::query_as!(
             Self,
             r#"
-            INSERT INTO "User" (name, password_hash, rank)
-                VALUES ($1, crypt($2, gen_salt('md5')), $3)
-            RETURNING *
+            INSERT INTO "User" (name, password_hash, parent_id, role, project_id)
+                VALUES ($1, crypt($2, gen_salt('md5')), $3, $4, $5)
+            RETURNING id, name, password_hash, parent_id, role as "role: _", project_id, must_change_password,
+                totp_secret, totp_enabled, NULL::access_token_scope AS "access_token_scope?: _"
             "#,
             name,
             password,
-            rank,
+            parent.id,
+            role as Role,
+            project_id,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?)
     }
 
+    /// Create the root user on the first start, from the configured name and password.
+    ///
+    /// If no password is configured, a random one is generated and logged. Either way, it must be
+    /// changed before using the API.
+    ///
+    /// Panics if the configured name is not a valid user name.
+    pub(crate) async fn create_root(pool: &PgPool) -> Result<()> {
+        if sqlx::query_scalar!(r#"SELECT EXISTS (SELECT FROM "User" WHERE parent_id IS NULL)"#)
+            .fetch_one(pool)
+            .await
+            .map(|option| option.expect("NULL from SELECT scalar"))?
+        {
+            return Ok(());
+        }
+        assert!(
+            validate_name(&CONFIG.admin_name).is_ok(),
+            "MERCURY_ADMIN_NAME must be 4 to 16 characters long and contain no ':'",
+        );
+        let password = match &CONFIG.admin_password {
+            Some(password) => password.clone(),
+            None => {
+                let password =
+                    sqlx::query_scalar!(r#"SELECT encode(gen_random_bytes(18), 'base64')"#)
+                        .fetch_one(pool)
+                        .await
+                        .map(|option| option.expect("NULL from SELECT scalar"))?;
+                warn!(
+                    name = CONFIG.admin_name,
+                    password,
+                    "generated the password of the root user, change it before using the API",
+                );
+                password
+            }
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO "User" (name, password_hash, role, must_change_password)
+                VALUES ($1, crypt($2, gen_salt('md5')), 'owner', true)
+            "#,
+            CONFIG.admin_name,
+            password,
+        )
+        .execute(pool)
+        .await?;
+        info!(name = CONFIG.admin_name, "created the root user");
+        Ok(())
+    }
+
     /// Get a user.
     pub(crate) async fn get(pool: &PgPool, id: Uuid) -> Result<Self> {
         sqlx::query_as!(
             Self,
             r#"
-            SELECT * FROM "User"
+            SELECT id, name, password_hash, parent_id, role as "role: _", project_id, must_change_password,
+                totp_secret, totp_enabled, NULL::access_token_scope AS "access_token_scope?: _"
+                FROM "User"
                 WHERE id = $1
             "#,
             id,
@@ -57,6 +241,9 @@ impl User {
     }
 
     /// Get a user by its name and password.
+    ///
+    /// Unknown names and wrong passwords fail the same way and take the same time, so that they
+    /// do not reveal which users exist.
     pub(crate) async fn get_by_name_and_password(
         pool: &PgPool,
         name: &str,
@@ -65,35 +252,85 @@ impl User {
         let user = sqlx::query_as!(
             Self,
             r#"
-            SELECT * FROM "User"
+            SELECT id, name, password_hash, parent_id, role as "role: _", project_id, must_change_password,
+                totp_secret, totp_enabled, NULL::access_token_scope AS "access_token_scope?: _"
+                FROM "User"
                 WHERE name = $1
             "#,
             name,
         )
         .fetch_optional(pool)
-        .await?
-        .ok_or(Error::NotFound)?;
-        // check if the password is correct
-        if sqlx::query_scalar!(r#"SELECT $1 = crypt($2, $1)"#, user.password_hash, password)
+        .await?;
+        // check the password even if the user does not exist
+        let password_hash = user
+            .as_ref()
+            .map_or(DUMMY_PASSWORD_HASH, |user| &user.password_hash);
+        if sqlx::query_scalar!(r#"SELECT $1 = crypt($2, $1)"#, password_hash, password)
             .fetch_one(pool)
             .await
             .map(|option| option.expect("NULL from SELECT scalar"))?
         {
-            Ok(user)
+            user.ok_or(Error::WrongCredentials)
         } else {
-            Err(Error::WrongPassword)
+            Err(Error::WrongCredentials)
         }
     }
 
-    /// Get all users whose rank is greater than or equal to `min_rank`.
-    pub(crate) async fn get_all(pool: &PgPool, min_rank: i32) -> Result<Vec<Self>> {
+    /// Get a user by its name and password and check their second factor, counting the failed
+    /// attempts by name and by client IP.
+    ///
+    /// Failed attempts are answered after a delay growing with the number of recent failures, and
+    /// names and IPs with too many recent failures are locked out.
+    pub(crate) async fn authenticate(
+        state: &SharedState,
+        ip: IpAddr,
+        name: &str,
+        password: &str,
+        code: Option<&str>,
+    ) -> Result<Self> {
+        let delay = state
+            .write()
+            .await
+            .login_attempts
+            .begin(name, ip)
+            .map_err(Error::TooManyAttempts)?;
+        let pool = state.read().await.pool.clone();
+        let result = match User::get_by_name_and_password(&pool, name, password).await {
+            Ok(user) => user.check_second_factor(&pool, code).await.map(|()| user),
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(user) => {
+                state.write().await.login_attempts.succeed(name, ip);
+                Ok(user)
+            }
+            Err(error @ (Error::WrongCredentials | Error::InvalidTotpCode)) => {
+                tokio::time::sleep(delay).await;
+                Err(error)
+            }
+            Err(error) => {
+                state.write().await.login_attempts.cancel(name, ip);
+                Err(error)
+            }
+        }
+    }
+
+    /// Get all the descendants of a user: their children, their children's children, and so on.
+    pub(crate) async fn get_descendants(pool: &PgPool, user: &User) -> Result<Vec<Self>> {
         Ok(sqlx::query_as!(
             Self,
             r#"
-            SELECT * FROM "User"
-                WHERE rank >= $1
+            WITH RECURSIVE descendant AS (
+                SELECT id FROM "User" WHERE parent_id = $1
+                UNION
+                SELECT "User".id FROM "User" JOIN descendant ON "User".parent_id = descendant.id
+            )
+            SELECT id, name, password_hash, parent_id, role as "role: _", project_id, must_change_password,
+                totp_secret, totp_enabled, NULL::access_token_scope AS "access_token_scope?: _"
+                FROM "User"
+                WHERE id IN (SELECT id FROM descendant)
             "#,
-            min_rank,
+            user.id,
         )
         .fetch_all(pool)
         .await?)
@@ -121,7 +358,8 @@ impl User {
         self.password_hash = sqlx::query_scalar!(
             r#"
             UPDATE "User"
-                SET password_hash = crypt($1, gen_salt('md5'))
+                SET password_hash = crypt($1, gen_salt('md5')),
+                    must_change_password = false
                 WHERE id = $2
             RETURNING password_hash
             "#,
@@ -130,14 +368,84 @@ impl User {
         )
         .fetch_one(pool)
         .await?;
+        self.must_change_password = false;
+        Ok(())
+    }
+
+    /// Change the user's role.
+    pub(crate) async fn set_role(&mut self, pool: &PgPool, role: Role) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "User"
+                SET role = $1
+                WHERE id = $2
+            "#,
+            role as Role,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        self.role = role;
         Ok(())
     }
 
-    /// Delete the user.
-    pub(crate) async fn delete(self, pool: &PgPool) -> Result<()> {
-        if self.rank == 0 {
+    /// Move the user under another parent.
+    pub(crate) async fn set_parent(&mut self, pool: &PgPool, parent: &User) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "User"
+                SET parent_id = $1
+                WHERE id = $2
+            "#,
+            parent.id,
+            self.id,
+        )
+        .execute(pool)
+        .await?;
+        self.parent_id = Some(parent.id);
+        Ok(())
+    }
+
+    /// Delete the user, and give their children and the channels and keys they created to the
+    /// heir.
+    pub(crate) async fn delete(self, pool: &PgPool, heir: &User) -> Result<()> {
+        if self.parent_id.is_none() {
             panic!("cannot delete root user");
         }
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            UPDATE "User"
+                SET parent_id = $2
+                WHERE parent_id = $1
+            "#,
+            self.id,
+            heir.id,
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE "Channel"
+                SET created_by = $2
+                WHERE created_by = $1
+            "#,
+            self.id,
+            heir.id,
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE "Key"
+                SET created_by = $2
+                WHERE created_by = $1
+            "#,
+            self.id,
+            heir.id,
+        )
+        .execute(&mut transaction)
+        .await?;
         sqlx::query!(
             r#"
                 DELETE FROM "User"
@@ -145,10 +453,247 @@ impl User {
                 "#,
             self.id,
         )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+impl User {
+    /// Check that the user's role has the permission.
+    pub(crate) fn require(&self, permission: Permission) -> Result<()> {
+        if self.role.has(permission)
+            && self
+                .access_token_scope
+                .is_none_or(|scope| scope.allows(permission))
+        {
+            Ok(())
+        } else {
+            Err(Error::MissingPermission(permission))
+        }
+    }
+
+    /// Returns whether the user can access the project.
+    pub(crate) fn can_access(&self, project_id: Uuid) -> bool {
+        self.project_id.is_none_or(|id| id == project_id)
+    }
+
+    /// Returns whether the other user is this one or one of their descendants.
+    pub(crate) async fn is_ancestor_of(&self, pool: &PgPool, other: &User) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar!(r#"SELECT user_descends_from($1, $2)"#, other.id, self.id)
+                .fetch_one(pool)
+                .await?
+                .expect("NULL from SELECT scalar"),
+        )
+    }
+
+    /// Returns whether the other user is one of this one's descendants, and so is managed by them.
+    pub(crate) async fn manages(&self, pool: &PgPool, other: &User) -> Result<bool> {
+        Ok(self.id != other.id && self.is_ancestor_of(pool, other).await?)
+    }
+
+    /// Returns whether the user can manage the other user's project.
+    pub(crate) fn reaches(&self, other: &User) -> bool {
+        self.project_id.is_none() || self.project_id == other.project_id
+    }
+
+    /// The project to create channels and keys in: the user's own, or the requested one if they
+    /// can access all projects.
+    pub(crate) fn project(&self, requested: Option<Uuid>) -> Result<Uuid> {
+        match (self.project_id, requested) {
+            (Some(id), Some(requested)) if id != requested => Err(Error::OtherProject),
+            (Some(id), _) | (None, Some(id)) => Ok(id),
+            (None, None) => Err(Error::MissingProject),
+        }
+    }
+}
+
+/// A user, even one who must change their password before using the rest of the API.
+#[derive(Debug)]
+pub(crate) struct AnyUser(pub(crate) User);
+
+/// Two-factor authentication
+impl User {
+    /// The TOTP generator for a secret: 6 digits every 30 seconds, accepting the previous and the
+    /// next code.
+    ///
+    /// Unchecked, so that names from before ':' was rejected in them cannot fail authentication:
+    /// the name is only the label shown by authenticator apps.
+    fn totp(&self, secret: Vec<u8>) -> TOTP {
+        TOTP::new_unchecked(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret,
+            Some("Mercury".to_owned()),
+            self.name.clone(),
+        )
+    }
+
+    /// Generate a new TOTP secret, which must be confirmed with a code before it is required.
+    pub(crate) async fn start_totp_enrolment(&mut self, pool: &PgPool) -> Result<TotpEnrolment> {
+        if self.totp_enabled {
+            return Err(Error::TotpAlreadyEnabled);
+        }
+        let secret = sqlx::query_scalar!(
+            r#"
+            UPDATE "User"
+                SET totp_secret = gen_random_bytes(20)
+                WHERE id = $1
+            RETURNING totp_secret as "totp_secret!"
+            "#,
+            self.id,
+        )
+        .fetch_one(pool)
+        .await?;
+        let totp = self.totp(secret.clone());
+        self.totp_secret = Some(secret);
+        Ok(TotpEnrolment {
+            secret: totp.get_secret_base32(),
+            uri: totp.get_url(),
+        })
+    }
+
+    /// Require TOTP codes from now on, if the code matches the secret being enrolled.
+    ///
+    /// Returns new recovery codes, which will only be returned once.
+    pub(crate) async fn confirm_totp_enrolment(
+        &mut self,
+        pool: &PgPool,
+        code: &str,
+    ) -> Result<Vec<String>> {
+        if self.totp_enabled {
+            return Err(Error::TotpAlreadyEnabled);
+        }
+        let secret = self.totp_secret.clone().ok_or(Error::NoTotpEnrolment)?;
+        if !self.check_totp_code(secret, code) {
+            return Err(Error::InvalidTotpCode);
+        }
+        sqlx::query!(
+            r#"
+            UPDATE "User"
+                SET totp_enabled = true
+                WHERE id = $1
+            "#,
+            self.id,
+        )
         .execute(pool)
         .await?;
+        self.totp_enabled = true;
+        self.new_recovery_codes(pool).await
+    }
+
+    /// Replace the user's recovery codes with new ones, which will only be returned once.
+    ///
+    /// Each recovery code can be used once instead of a TOTP code.
+    pub(crate) async fn new_recovery_codes(&self, pool: &PgPool) -> Result<Vec<String>> {
+        if !self.totp_enabled {
+            return Err(Error::NoTotpEnrolment);
+        }
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM "RecoveryCode"
+                WHERE user_id = $1
+            "#,
+            self.id,
+        )
+        .execute(&mut transaction)
+        .await?;
+        let codes = sqlx::query_scalar!(
+            r#"
+            SELECT encode(gen_random_bytes(5), 'hex') as "code!"
+                FROM generate_series(1, 10)
+            "#
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO "RecoveryCode" (user_id, code_hash)
+                SELECT $1, digest(code, 'sha256') FROM unnest($2::text[]) AS code
+            "#,
+            self.id,
+            &codes,
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(codes)
+    }
+
+    /// Stop requiring TOTP codes, and delete the secret and the recovery codes.
+    pub(crate) async fn disable_totp(&mut self, pool: &PgPool) -> Result<()> {
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"
+            UPDATE "User"
+                SET totp_secret = NULL,
+                    totp_enabled = false
+                WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM "RecoveryCode"
+                WHERE user_id = $1
+            "#,
+            self.id,
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        self.totp_secret = None;
+        self.totp_enabled = false;
         Ok(())
     }
+
+    /// Returns whether the code is the current TOTP code of the secret.
+    fn check_totp_code(&self, secret: Vec<u8>, code: &str) -> bool {
+        self.totp(secret)
+            .check_current(code)
+            .expect("system time before the UNIX epoch")
+    }
+
+    /// Check the second factor of a user who authenticates with their password, if they enrolled
+    /// one: a TOTP code, or a recovery code which can then not be used again.
+    pub(crate) async fn check_second_factor(
+        &self,
+        pool: &PgPool,
+        code: Option<&str>,
+    ) -> Result<()> {
+        let secret = match &self.totp_secret {
+            Some(secret) if self.totp_enabled => secret.clone(),
+            _ => return Ok(()),
+        };
+        let code = code.ok_or(Error::TotpRequired)?;
+        if self.check_totp_code(secret, code) {
+            return Ok(());
+        }
+        let recovery_code_used = sqlx::query!(
+            r#"
+            DELETE FROM "RecoveryCode"
+                WHERE user_id = $1 AND code_hash = digest($2, 'sha256')
+            "#,
+            self.id,
+            code,
+        )
+        .execute(pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if recovery_code_used {
+            Ok(())
+        } else {
+            Err(Error::InvalidTotpCode)
+        }
+    }
 }
 
 #[async_trait]
@@ -159,41 +704,118 @@ where
 {
     type Rejection = Error;
 
+    /// Use the `AnyUser` extractor, and refuse users who must change their password.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        let AnyUser(user) = AnyUser::from_request_parts(parts, state).await?;
+        if user.must_change_password {
+            Err(Error::PasswordChangeRequired)
+        } else {
+            Ok(user)
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AnyUser
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    type Rejection = Error;
+
+    /// Use the session token or access token if one is given, as a Bearer token or in the
+    /// `mercury_session` cookie, or Basic credentials otherwise, with a TOTP code in the
+    /// `x-mercury-totp` header if the user enrolled one.
+    ///
+    /// Users authenticated with an access token are limited to its scope.
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        if let Some(token) = Session::token_from_request_parts(parts, state).await {
+            let state = SharedState::from_ref(state);
+            if token.starts_with(ACCESS_TOKEN_PREFIX) {
+                let access_token = AccessToken::use_token(&state.read().await.pool, &token).await?;
+                let mut user = User::get(&state.read().await.pool, access_token.user_id).await?;
+                user.access_token_scope = Some(access_token.scope);
+                return Ok(Self(user));
+            }
+            let session = Session::get_by_token(&state.read().await.pool, &token).await?;
+            return Ok(Self(
+                User::get(&state.read().await.pool, session.user_id).await?,
+            ));
+        }
+
         let authorization_header =
             TypedHeader::<Authorization<Basic>>::from_request_parts(parts, state).await?;
 
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state)
+            .await
+            .expect("infallible");
+
         let state = SharedState::from_ref(state);
 
-        let user = Self::get_by_name_and_password(
-            &state.read().await.pool,
+        let code = parts
+            .headers
+            .get(TOTP_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let user = User::authenticate(
+            &state,
+            ip,
             authorization_header.username(),
             authorization_header.password(),
+            code,
         )
         .await?;
 
-        Ok(user)
+        Ok(Self(user))
     }
 }
 
 pub(crate) mod error {
+    use std::time::Duration;
+
     use axum::extract::rejection::TypedHeaderRejection;
+    use axum::http::header::RETRY_AFTER;
     use axum::response::IntoResponse;
     use hyper::StatusCode;
     use tracing::{debug, error};
 
+    use super::Permission;
+    use crate::models::{access_token, session};
+
     pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+    #[allow(clippy::enum_variant_names)]
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum Error {
         #[error(transparent)]
         TypedHeaderRejection(#[from] TypedHeaderRejection),
+        #[error(transparent)]
+        SessionError(#[from] session::error::Error),
+        #[error(transparent)]
+        AccessTokenError(#[from] access_token::error::Error),
         #[error("User not found")]
         NotFound,
-        #[error("Wrong password")]
-        WrongPassword,
+        #[error("Wrong name or password")]
+        WrongCredentials,
+        #[error("Too many failed login attempts")]
+        TooManyAttempts(Duration),
         #[error("Duplicate user name")]
         DuplicateName,
+        #[error("Missing permission: {0:?}")]
+        MissingPermission(Permission),
+        #[error("Missing project")]
+        MissingProject,
+        #[error("Password change required")]
+        PasswordChangeRequired,
+        #[error("TOTP code required")]
+        TotpRequired,
+        #[error("Invalid TOTP code")]
+        InvalidTotpCode,
+        #[error("TOTP already enabled")]
+        TotpAlreadyEnabled,
+        #[error("No TOTP enrolment")]
+        NoTotpEnrolment,
+        #[error("Cannot access another project")]
+        OtherProject,
     }
 
     impl From<sqlx::Error> for Error {
@@ -213,11 +835,39 @@ pub(crate) mod error {
             debug!(?self);
             match self {
                 Error::TypedHeaderRejection(error) => error.into_response(),
+                Error::SessionError(error) => error.into_response(),
+                Error::AccessTokenError(error) => error.into_response(),
                 Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
-                Error::WrongPassword => {
+                Error::WrongCredentials => {
                     (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
                 }
+                Error::TooManyAttempts(retry_after) => (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string())],
+                    self.to_string(),
+                )
+                    .into_response(),
                 Error::DuplicateName => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+                Error::MissingPermission(_) => {
+                    (StatusCode::FORBIDDEN, self.to_string()).into_response()
+                }
+                Error::MissingProject => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+                }
+                Error::OtherProject => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+                Error::PasswordChangeRequired => {
+                    (StatusCode::FORBIDDEN, self.to_string()).into_response()
+                }
+                Error::TotpRequired => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+                Error::InvalidTotpCode => {
+                    (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+                }
+                Error::TotpAlreadyEnabled => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+                }
+                Error::NoTotpEnrolment => {
+                    (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+                }
             }
         }
     }
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+/// A rate limit, as a number of tokens per second and a maximum burst of tokens.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: f64,
+    pub(crate) burst: f64,
+}
+
+/// A token bucket limiting publications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum PublishBucket {
+    KeyMessages(Uuid),
+    KeyBytes(Uuid),
+    ChannelMessages(Uuid),
+    ChannelBytes(Uuid),
+}
+
+impl PublishBucket {
+    /// Whether the bucket counts bytes rather than messages.
+    pub(crate) fn counts_bytes(self) -> bool {
+        matches!(self, Self::KeyBytes(_) | Self::ChannelBytes(_))
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// When the bucket will be full again, after which it can be forgotten.
+    full_at: Instant,
+}
+
+/// Token buckets, one for each key.
+#[derive(Debug)]
+pub(crate) struct RateLimiter<K>(HashMap<K, TokenBucket>);
+
+impl<K> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<K: Eq + Hash + Copy> RateLimiter<K> {
+    /// Take `cost` tokens from the bucket of `key`.
+    ///
+    /// If there are not enough tokens, returns how long to wait before retrying.
+    pub(crate) fn check(&mut self, key: K, limit: RateLimit, cost: f64) -> Result<(), Duration> {
+        self.check_all(&[(key, limit, cost)])
+    }
+
+    /// Take tokens from several buckets, from none of them if any does not have enough tokens.
+    ///
+    /// If there are not enough tokens, returns how long to wait before retrying.
+    pub(crate) fn check_all(&mut self, costs: &[(K, RateLimit, f64)]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut retry_after = Duration::ZERO;
+        for &(key, limit, cost) in costs {
+            let bucket = self.0.entry(key).or_insert(TokenBucket {
+                tokens: limit.burst,
+                updated_at: now,
+                full_at: now,
+            });
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst);
+            bucket.updated_at = now;
+            if bucket.tokens < cost {
+                retry_after = retry_after.max(Duration::from_secs_f64(
+                    (cost - bucket.tokens) / limit.per_second,
+                ));
+            }
+        }
+        if !retry_after.is_zero() {
+            return Err(retry_after);
+        }
+        for &(key, limit, cost) in costs {
+            let bucket = self.0.get_mut(&key).expect("bucket created above");
+            bucket.tokens -= cost;
+            bucket.full_at =
+                now + Duration::from_secs_f64((limit.burst - bucket.tokens) / limit.per_second);
+        }
+        Ok(())
+    }
+
+    /// Give back the tokens taken by `check_all`, for a request rejected afterwards.
+    pub(crate) fn refund(&mut self, costs: &[(K, RateLimit, f64)]) {
+        for &(key, limit, cost) in costs {
+            if let Some(bucket) = self.0.get_mut(&key) {
+                bucket.tokens = (bucket.tokens + cost).min(limit.burst);
+                bucket.full_at = bucket.updated_at
+                    + Duration::from_secs_f64((limit.burst - bucket.tokens) / limit.per_second);
+            }
+        }
+    }
+
+    /// Forget the buckets that are full.
+    pub(crate) fn prune(&mut self) {
+        let now = Instant::now();
+        self.0.retain(|_, bucket| bucket.full_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        per_second: 1.0,
+        burst: 2.0,
+    };
+
+    #[test]
+    fn check_allows_bursts() {
+        let mut rate_limiter = RateLimiter::default();
+        assert!(rate_limiter.check(1, LIMIT, 1.0).is_ok());
+        assert!(rate_limiter.check(1, LIMIT, 1.0).is_ok());
+        let retry_after = rate_limiter.check(1, LIMIT, 1.0).unwrap_err();
+        assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1));
+        // other keys have their own bucket
+        assert!(rate_limiter.check(2, LIMIT, 2.0).is_ok());
+    }
+
+    #[test]
+    fn check_all_takes_from_none_of_the_buckets_if_one_is_empty() {
+        let mut rate_limiter = RateLimiter::default();
+        assert!(rate_limiter.check(1, LIMIT, 2.0).is_ok());
+        assert!(rate_limiter
+            .check_all(&[(2, LIMIT, 1.0), (1, LIMIT, 1.0)])
+            .is_err());
+        // the first bucket was left untouched
+        assert!(rate_limiter.check(2, LIMIT, 2.0).is_ok());
+    }
+
+    #[test]
+    fn refund_gives_back_the_tokens() {
+        let mut rate_limiter = RateLimiter::default();
+        let costs = [(1, LIMIT, 1.0), (2, LIMIT, 2.0)];
+        assert!(rate_limiter.check_all(&costs).is_ok());
+        rate_limiter.refund(&costs);
+        assert!(rate_limiter.check(1, LIMIT, 2.0).is_ok());
+        assert!(rate_limiter.check(2, LIMIT, 2.0).is_ok());
+    }
+
+    #[test]
+    fn prune_forgets_full_buckets_only() {
+        let mut rate_limiter = RateLimiter::default();
+        assert!(rate_limiter.check(1, LIMIT, 0.0).is_ok());
+        assert!(rate_limiter.check(2, LIMIT, 1.0).is_ok());
+        rate_limiter.prune();
+        assert_eq!(rate_limiter.0.len(), 1);
+        assert!(rate_limiter.0.contains_key(&2));
+    }
+}
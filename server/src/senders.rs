@@ -21,6 +21,13 @@ impl Senders {
         }
     }
 
+    /// Get the number of live subscribers of a channel.
+    pub(crate) fn subscriber_count(&self, channel: &Channel) -> usize {
+        self.0
+            .get(&channel.id)
+            .map_or(0, |sender| sender.receiver_count())
+    }
+
     pub(crate) fn get_receiver(&mut self, channel: &Channel) -> broadcast::Receiver<Value> {
         self.get(channel).subscribe()
     }
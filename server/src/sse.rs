@@ -1,60 +1,386 @@
 use std::convert::Infallible;
 
-use axum::extract::{Path, State};
+use axum::extract::{FromRef, FromRequestParts, Path, State};
+use axum::http::header::{
+    HeaderName, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, AUTHORIZATION, VARY,
+};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::routing::get;
-use axum::{Json, Router};
+use axum::{async_trait, Json, Router};
 use futures::stream::Stream;
+use serde::Deserialize;
 use serde_json::Value;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tracing::{error, instrument};
+use uuid::Uuid;
 
 use self::error::{Error, Result};
+use crate::api::extract::client_ip::ClientIp;
+use crate::api::extract::key::{key_from_token, subscriber_token_from_request_parts};
+use crate::api::extract::origin::RequestOrigin;
+use crate::authorizations;
+use crate::config::CONFIG;
+use crate::connections::ConnectionScope;
 use crate::models::channel::Channel;
-use crate::models::key::Key;
+use crate::models::key::{Capability, Key};
+use crate::models::project::{Project, DEFAULT_PROJECT};
+use crate::models::token::Token;
+use crate::rate_limiter::{PublishBucket, RateLimit};
 use crate::state::SharedState;
 
 pub(crate) fn app(state: SharedState) -> Router<SharedState> {
-    Router::with_state(state).route("/:channel_name", get(subscribe).post(publish))
+    Router::with_state(state)
+        .route(
+            "/:channel_name",
+            get(subscribe).post(publish).options(preflight),
+        )
+        .route(
+            "/projects/:project/:channel_name",
+            get(subscribe).post(publish).options(preflight),
+        )
+        // so that browsers can read errors, which have no CORS headers of their own
+        .layer(SetResponseHeaderLayer::if_not_present(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            VARY,
+            HeaderValue::from_static("origin"),
+        ))
+}
+
+/// The path of a channel: its name, after the name of its project unless the credentials are
+/// enough to know it.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChannelPath {
+    project: Option<String>,
+    channel_name: String,
+}
+
+impl ChannelPath {
+    /// Get the channel, in the project named in the path, or else in the project of the
+    /// credentials, or else in the default project.
+    ///
+    /// Credentials never authorize the channels of another project.
+    async fn get_channel(&self, state: &SharedState, project_id: Option<Uuid>) -> Result<Channel> {
+        let project_id = match (&self.project, project_id) {
+            (Some(name), project_id) => {
+                let project = Project::get_by_name(&state.read().await.pool, name).await?;
+                if project_id.is_some_and(|project_id| project_id != project.id) {
+                    return Err(Error::UnauthorizedChannel);
+                }
+                project.id
+            }
+            (None, Some(project_id)) => project_id,
+            // anonymous subscribers to channels named without their project
+            (None, None) => {
+                Project::get_by_name(&state.read().await.pool, DEFAULT_PROJECT)
+                    .await?
+                    .id
+            }
+        };
+        Ok(Channel::get_by_name(&state.read().await.pool, project_id, &self.channel_name).await?)
+    }
+}
+
+/// The CORS headers of a response: the request's origin if the key restricts origins, which the
+/// `Key` extractor already checked, and any origin otherwise.
+fn cors_headers(origin: RequestOrigin, key: Option<&Key>) -> [(HeaderName, HeaderValue); 2] {
+    let allow_origin = match (key, origin.0) {
+        (Some(key), Some(origin)) if key.restricts_origins() => origin,
+        _ => HeaderValue::from_static("*"),
+    };
+    [
+        (ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin),
+        (VARY, HeaderValue::from_static("origin")),
+    ]
+}
+
+/// Answer CORS preflight requests from any origin.
+///
+/// Preflight requests carry no credentials, the actual request is checked against the allowed
+/// origins of its key.
+async fn preflight(origin: RequestOrigin) -> (StatusCode, [(HeaderName, HeaderValue); 5]) {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (
+                ACCESS_CONTROL_ALLOW_ORIGIN,
+                origin.0.unwrap_or(HeaderValue::from_static("*")),
+            ),
+            (
+                ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static("GET, POST"),
+            ),
+            (
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_static("authorization, content-type, last-event-id"),
+            ),
+            (ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("86400")),
+            (VARY, HeaderValue::from_static("origin")),
+        ],
+    )
+}
+
+/// The credentials of a subscriber: either a key, a signed token, or none for public channels.
+#[derive(Debug)]
+pub(crate) enum Subscriber {
+    Key(Box<Key>),
+    Token(Token),
+    Anonymous,
+}
+
+impl Subscriber {
+    /// The project of the subscriber's key or token.
+    fn project_id(&self) -> Option<Uuid> {
+        match self {
+            Self::Key(key) => Some(key.project_id),
+            Self::Token(token) => token.project,
+            Self::Anonymous => None,
+        }
+    }
+
+    /// Identify the subscriber to authorization webhooks, as `key:{id}` or the token's subject.
+    fn identity(&self) -> Option<String> {
+        match self {
+            Self::Key(key) => Some(format!("key:{}", key.id)),
+            Self::Token(token) => Some(token.sub.clone()),
+            Self::Anonymous => None,
+        }
+    }
+
+    /// Returns whether the subscriber is authorized to subscribe to the channel.
+    async fn authorizes(&self, state: &SharedState, channel: &Channel) -> Result<bool> {
+        if channel.public {
+            return Ok(true);
+        }
+        match self {
+            Self::Key(key) => Ok(key
+                .authorizes(&state.read().await.pool, channel, Capability::Subscribe)
+                .await?),
+            Self::Token(token) => Ok(token.authorizes(channel)),
+            Self::Anonymous => Ok(false),
+        }
+    }
+
+    /// Returns whether the channel's authorization webhook, if it has one, authorizes the
+    /// subscriber.
+    async fn authorized_by_webhook(&self, state: &SharedState, channel: &Channel) -> Result<bool> {
+        match &channel.authorization_url {
+            Some(url) => Ok(authorizations::check(state, channel, url, self.identity()).await?),
+            None => Ok(true),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Subscriber
+where
+    S: Send + Sync,
+    SharedState: FromRef<S>,
+{
+    type Rejection = Error;
+
+    /// Verify the signed token if one is given, without touching the database, or else check the
+    /// key. Unlike with the `Key` extractor, tokens can also be given in the `token` query
+    /// parameter or in the `mercury_token` cookie. Without any credentials, the subscriber is
+    /// anonymous.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        match subscriber_token_from_request_parts(parts, state).await? {
+            Some(token) if Token::is_token(&token) => {
+                let state = SharedState::from_ref(state);
+                let token = Token::verify(&state.read().await.token_keys, &token)?;
+                Ok(Self::Token(token))
+            }
+            Some(token) => Ok(Self::Key(Box::new(
+                key_from_token(parts, state, &token).await?,
+            ))),
+            None if !parts.headers.contains_key(AUTHORIZATION) => Ok(Self::Anonymous),
+            None => Ok(Self::Key(Box::new(
+                Key::from_request_parts(parts, state).await?,
+            ))),
+        }
+    }
 }
 
 #[instrument]
 pub(crate) async fn subscribe(
     State(state): State<SharedState>,
-    key: Key,
-    Path(channel_name): Path<String>,
-) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
-    let channel = Channel::get_by_name(&state.read().await.pool, &channel_name).await?;
-    if key.is_subscriber() && key.authorizes(&state.read().await.pool, &channel).await? {
+    subscriber: Subscriber,
+    ClientIp(ip): ClientIp,
+    origin: RequestOrigin,
+    Path(path): Path<ChannelPath>,
+) -> Result<(
+    [(HeaderName, HeaderValue); 2],
+    Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>,
+)> {
+    if let Subscriber::Anonymous = subscriber {
+        let limit = RateLimit {
+            per_second: CONFIG.anonymous_subscriptions_per_second,
+            burst: CONFIG.anonymous_subscriptions_burst,
+        };
+        state
+            .write()
+            .await
+            .anonymous_rate_limiter
+            .check(ip, limit, 1.0)
+            .map_err(Error::RateLimited)?;
+    }
+    let channel = path.get_channel(&state, subscriber.project_id()).await?;
+    if subscriber.authorizes(&state, &channel).await?
+        && subscriber.authorized_by_webhook(&state, &channel).await?
+    {
+        let mut limits = vec![
+            (
+                ConnectionScope::Channel(channel.id),
+                CONFIG.max_connections_per_channel,
+            ),
+            (ConnectionScope::Ip(ip), CONFIG.max_connections_per_ip),
+        ];
+        match &subscriber {
+            Subscriber::Key(key) => {
+                limits.push((ConnectionScope::Key(key.id), CONFIG.max_connections_per_key));
+            }
+            Subscriber::Token(_) => {}
+            Subscriber::Anonymous => limits.push((
+                ConnectionScope::AnonymousChannel(channel.id),
+                CONFIG.anonymous_connections_per_channel,
+            )),
+        }
+        // dropped with the stream, when the subscriber disconnects
+        let connection = state
+            .read()
+            .await
+            .connections
+            .acquire(&limits)
+            .map_err(Error::TooManyConnections)?;
+        if let Subscriber::Key(key) = &subscriber {
+            state.write().await.key_usage.record_subscribe(key);
+        }
         let receiver = state.write().await.senders.get_receiver(&channel);
-        let stream = BroadcastStream::new(receiver).filter_map(|result| match result {
-            Ok(value) => Some(Ok(Event::default()
-                .json_data(value)
-                .expect("invalid JSON from channel"))),
-            Err(error) => {
-                error!(?error);
-                None
+        let stream = BroadcastStream::new(receiver).filter_map(move |result| {
+            let _connection = &connection;
+            match result {
+                Ok(value) => Some(Ok(Event::default()
+                    .json_data(value)
+                    .expect("invalid JSON from channel"))),
+                Err(error) => {
+                    error!(?error);
+                    None
+                }
             }
         });
-        Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+        let key = match &subscriber {
+            Subscriber::Key(key) => Some(key.as_ref()),
+            _ => None,
+        };
+        Ok((
+            cors_headers(origin, key),
+            Sse::new(stream).keep_alive(KeepAlive::default()),
+        ))
     } else {
         Err(Error::UnauthorizedChannel)
     }
 }
 
+/// The rate limits and costs of publishing a message, from the limits in messages or bytes per
+/// second of each bucket, if any.
+///
+/// The limits allow bursts of one second worth of messages or bytes, and at least one message, so
+/// that limits below one message per second still let messages through. A message larger than a
+/// byte rate limit can never go through. Limits that are not positive are ignored, though the
+/// database rejects them.
+fn publish_costs(
+    limits: [(PublishBucket, Option<f64>, f64); 4],
+) -> Result<Vec<(PublishBucket, RateLimit, f64)>> {
+    let mut costs = Vec::new();
+    for (bucket, per_second, cost) in limits {
+        if let Some(per_second) = per_second.filter(|&per_second| per_second > 0.0) {
+            if bucket.counts_bytes() && cost > per_second {
+                return Err(Error::MessageTooLarge);
+            }
+            let limit = RateLimit {
+                per_second,
+                burst: per_second.max(cost),
+            };
+            costs.push((bucket, limit, cost));
+        }
+    }
+    Ok(costs)
+}
+
+/// Take a message of `size` bytes from the rate limits of the key and the channel.
+///
+/// Returns what was taken, to refund it if the message is rejected afterwards.
+async fn check_publish_limits(
+    state: &SharedState,
+    key: &Key,
+    channel: &Channel,
+    size: f64,
+) -> Result<Vec<(PublishBucket, RateLimit, f64)>> {
+    let limits = [
+        (
+            PublishBucket::KeyMessages(key.id),
+            key.publish_rate_limit,
+            1.0,
+        ),
+        (
+            PublishBucket::KeyBytes(key.id),
+            key.publish_byte_rate_limit,
+            size,
+        ),
+        (
+            PublishBucket::ChannelMessages(channel.id),
+            channel.publish_rate_limit,
+            1.0,
+        ),
+        (
+            PublishBucket::ChannelBytes(channel.id),
+            channel.publish_byte_rate_limit,
+            size,
+        ),
+    ];
+    let costs = publish_costs(limits)?;
+    state
+        .write()
+        .await
+        .publish_rate_limiter
+        .check_all(&costs)
+        .map_err(Error::RateLimited)?;
+    Ok(costs)
+}
+
 #[instrument]
 pub(crate) async fn publish(
     State(state): State<SharedState>,
     key: Key,
-    Path(channel_name): Path<String>,
+    origin: RequestOrigin,
+    Path(path): Path<ChannelPath>,
     Json(body): Json<Value>,
-) -> Result<String> {
-    let channel = Channel::get_by_name(&state.read().await.pool, &channel_name).await?;
-    if key.is_publisher() && key.authorizes(&state.read().await.pool, &channel).await? {
+) -> Result<([(HeaderName, HeaderValue); 2], String)> {
+    let channel = path.get_channel(&state, Some(key.project_id)).await?;
+    if key
+        .authorizes(&state.read().await.pool, &channel, Capability::Publish)
+        .await?
+    {
         if channel.is_valid(&body) {
+            let costs =
+                check_publish_limits(&state, &key, &channel, body.to_string().len() as f64).await?;
+            if let Err(error) = key.consume_quota(&state.read().await.pool).await {
+                state.write().await.publish_rate_limiter.refund(&costs);
+                return Err(error.into());
+            }
             let sender = state.write().await.senders.get(&channel);
-            Ok(format!("{}", sender.send(body).unwrap_or(0)))
+            state.write().await.key_usage.record_publish(&key);
+            Ok((
+                cors_headers(origin, Some(&key)),
+                format!("{}", sender.send(body).unwrap_or(0)),
+            ))
         } else {
             Err(Error::from(
                 channel
@@ -68,6 +394,9 @@ pub(crate) async fn publish(
 }
 
 mod error {
+    use std::time::Duration;
+
+    use axum::http::header::RETRY_AFTER;
     use axum::response::IntoResponse;
     use axum::Json;
     use hyper::StatusCode;
@@ -76,7 +405,8 @@ mod error {
     use serde_json::Value;
     use tracing::debug;
 
-    use crate::models::{channel, key};
+    use super::ConnectionScope;
+    use crate::models::{channel, key, project, token};
 
     #[derive(Debug, Serialize)]
     #[serde(rename_all = "camelCase")]
@@ -92,13 +422,25 @@ mod error {
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum Error {
         #[error(transparent)]
-        ChannelError(#[from] channel::error::Error),
+        Channel(#[from] channel::error::Error),
+        #[error(transparent)]
+        Key(#[from] key::error::Error),
         #[error(transparent)]
-        KeyError(#[from] key::error::Error),
+        Token(#[from] token::error::Error),
+        #[error(transparent)]
+        Project(#[from] project::error::Error),
+        #[error("Authorization webhook failed")]
+        Webhook(#[from] reqwest::Error),
         #[error("Invalid data")]
         InvalidData(Vec<ValidationError>),
         #[error("Unauthorized channel")]
         UnauthorizedChannel,
+        #[error("Too many requests")]
+        RateLimited(Duration),
+        #[error("Too many connections for this {}", .0.name())]
+        TooManyConnections(ConnectionScope),
+        #[error("Message larger than the byte rate limit")]
+        MessageTooLarge,
     }
 
     impl<'a> From<ErrorIterator<'a>> for Error {
@@ -120,15 +462,80 @@ mod error {
         fn into_response(self) -> axum::response::Response {
             debug!(?self);
             match self {
-                Error::ChannelError(error) => error.into_response(),
-                Error::KeyError(error) => error.into_response(),
+                Error::Channel(error) => error.into_response(),
+                Error::Key(error) => error.into_response(),
+                Error::Token(error) => error.into_response(),
+                Error::Project(error) => error.into_response(),
+                Error::Webhook(_) => (StatusCode::BAD_GATEWAY, self.to_string()).into_response(),
                 Error::InvalidData(errors) => {
                     (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response()
                 }
                 Error::UnauthorizedChannel => {
                     (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
                 }
+                Error::RateLimited(retry_after) => (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after.as_secs_f64().ceil().to_string())],
+                    self.to_string(),
+                )
+                    .into_response(),
+                // the subscriber's own connections are too many, or the server's
+                Error::TooManyConnections(ConnectionScope::Key(_) | ConnectionScope::Ip(_)) => {
+                    (StatusCode::TOO_MANY_REQUESTS, self.to_string()).into_response()
+                }
+                Error::TooManyConnections(_) => {
+                    (StatusCode::SERVICE_UNAVAILABLE, self.to_string()).into_response()
+                }
+                Error::MessageTooLarge => {
+                    (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()).into_response()
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limiter::RateLimiter;
+
+    fn limits(
+        messages: Option<f64>,
+        bytes: Option<f64>,
+        size: f64,
+    ) -> [(PublishBucket, Option<f64>, f64); 4] {
+        let id = Uuid::nil();
+        [
+            (PublishBucket::KeyMessages(id), messages, 1.0),
+            (PublishBucket::KeyBytes(id), bytes, size),
+            (PublishBucket::ChannelMessages(id), None, 1.0),
+            (PublishBucket::ChannelBytes(id), None, size),
+        ]
+    }
+
+    #[test]
+    fn publish_costs_skip_unlimited_buckets() {
+        assert!(publish_costs(limits(None, None, 100.0)).unwrap().is_empty());
+        assert!(publish_costs(limits(Some(0.0), Some(0.0), 100.0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn publish_costs_allow_less_than_one_message_per_second() {
+        let costs = publish_costs(limits(Some(0.5), None, 100.0)).unwrap();
+        let mut rate_limiter = RateLimiter::default();
+        assert!(rate_limiter.check_all(&costs).is_ok());
+        let retry_after = rate_limiter.check_all(&costs).unwrap_err();
+        assert!(retry_after.as_secs_f64() > 1.9 && retry_after.as_secs_f64() <= 2.0);
+    }
+
+    #[test]
+    fn publish_costs_reject_messages_larger_than_byte_limits() {
+        assert!(matches!(
+            publish_costs(limits(None, Some(10.0), 100.0)),
+            Err(Error::MessageTooLarge)
+        ));
+        assert!(publish_costs(limits(None, Some(100.0), 100.0)).is_ok());
+    }
+}
@@ -1,16 +1,34 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use anyhow::Result;
 use sqlx::PgPool;
 use tokio::sync::RwLock;
 
+use crate::authorizations::Authorizations;
+use crate::connections::{ConnectionScope, Connections};
 use crate::database::pool;
+use crate::key_usage::KeyUsage;
+use crate::login_attempts::LoginAttempts;
+use crate::models::token::TokenKeys;
+use crate::models::user::User;
+use crate::rate_limiter::{PublishBucket, RateLimiter};
 use crate::senders::Senders;
 
 #[derive(Debug)]
 pub struct AppState {
     pub(crate) pool: PgPool,
     pub(crate) senders: Senders,
+    pub(crate) token_keys: TokenKeys,
+    pub(crate) key_usage: KeyUsage,
+    /// Anonymous subscriptions, by client IP.
+    pub(crate) anonymous_rate_limiter: RateLimiter<IpAddr>,
+    /// Open subscriptions, by key, channel and client IP.
+    pub(crate) connections: Connections<ConnectionScope>,
+    pub(crate) authorizations: Authorizations,
+    /// Publications, by key and by channel.
+    pub(crate) publish_rate_limiter: RateLimiter<PublishBucket>,
+    pub(crate) login_attempts: LoginAttempts,
 }
 
 pub type SharedState = Arc<RwLock<AppState>>;
@@ -19,7 +37,19 @@ impl AppState {
     pub(crate) async fn new() -> Result<SharedState> {
         let pool = pool().await?;
         let senders = Senders::default();
-        let state = Arc::new(RwLock::new(AppState { pool, senders }));
+        let token_keys = TokenKeys::new(&pool).await?;
+        User::create_root(&pool).await?;
+        let state = Arc::new(RwLock::new(AppState {
+            pool,
+            senders,
+            token_keys,
+            key_usage: KeyUsage::default(),
+            anonymous_rate_limiter: RateLimiter::default(),
+            connections: Connections::default(),
+            authorizations: Authorizations::default(),
+            publish_rate_limiter: RateLimiter::default(),
+            login_attempts: LoginAttempts::default(),
+        }));
 
         Ok(state)
     }